@@ -0,0 +1,242 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{BackendError, BackendResponse, TranslationBackend, Usage};
+
+const DEFAULT_API_BASE: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+pub const DEFAULT_MODEL: &str = "claude-3-5-sonnet-latest";
+
+#[derive(Serialize, Debug)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    system: String,
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize, Debug)]
+struct Message {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum ContentBlock {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+/// Anthropic Messages API 后端
+#[derive(Debug)]
+pub struct Anthropic {
+    api_key: String,
+    api_base: String,
+    model: String,
+    temperature: Option<f32>,
+    client: reqwest::Client,
+}
+
+impl Anthropic {
+    pub fn new(api_key: &str, model: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            api_base: DEFAULT_API_BASE.to_string(),
+            model: model.to_string(),
+            temperature: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// 从响应中取出文本内容，并根据停止原因判断结果是否完整
+    fn parse_response(response: MessagesResponse) -> Result<BackendResponse, BackendError> {
+        if response.stop_reason.as_deref() == Some("max_tokens") {
+            return Err(BackendError::InvalidResponse(
+                "Response truncated by max_tokens".to_string(),
+            ));
+        }
+        let text: String = response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                ContentBlock::Other => None,
+            })
+            .collect();
+        if text.is_empty() {
+            return Err(BackendError::InvalidResponse(
+                "No Content Found".to_string(),
+            ));
+        }
+        let usage = response
+            .usage
+            .map(|u| Usage {
+                prompt_tokens: u.input_tokens,
+                completion_tokens: u.output_tokens,
+            })
+            .unwrap_or_default();
+        Ok(BackendResponse { text, usage })
+    }
+}
+
+#[async_trait]
+impl TranslationBackend for Anthropic {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn parameters(&self) -> String {
+        format!("temperature={:?}", self.temperature)
+    }
+
+    async fn chat(
+        &self,
+        prompt: String,
+        user_message: String,
+    ) -> Result<BackendResponse, BackendError> {
+        let request = MessagesRequest {
+            model: &self.model,
+            max_tokens: 4_000,
+            temperature: self.temperature,
+            system: prompt,
+            messages: vec![Message {
+                role: "user",
+                content: user_message,
+            }],
+        };
+        let response = self
+            .client
+            .post(format!("{}/v1/messages", self.api_base))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| BackendError::RequestError(e.to_string()))?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| BackendError::RequestError(e.to_string()))?;
+        if !status.is_success() {
+            let message = serde_json::from_str::<ErrorResponse>(&body)
+                .map(|e| format!("{}: {}", e.error.kind, e.error.message))
+                .unwrap_or(body);
+            return Err(BackendError::http(status, &headers, message));
+        }
+        let response: MessagesResponse = serde_json::from_str(&body)
+            .map_err(|e| BackendError::InvalidResponse(e.to_string()))?;
+        Self::parse_response(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockServer;
+
+    #[tokio::test]
+    async fn test_chat() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude-3-5-sonnet-latest","content":[{"type":"text","text":"你好"},{"type":"text","text":"？"}],"stop_reason":"end_turn","usage":{"input_tokens":20,"output_tokens":3}}"#.to_string(),
+        )])
+        .await;
+        let anthropic = Anthropic::new("test-key", DEFAULT_MODEL).with_api_base(&server.url());
+
+        let response = anthropic
+            .chat("system prompt".to_string(), "Hello?".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, "你好？");
+        assert_eq!(response.usage.prompt_tokens, 20);
+        assert_eq!(response.usage.completion_tokens, 3);
+        let request = &server.requests()[0];
+        assert!(request.starts_with("POST /v1/messages"));
+        assert!(request.contains("x-api-key: test-key"));
+        assert!(request.contains("anthropic-version: 2023-06-01"));
+        assert!(request.contains(r#""system":"system prompt""#));
+        assert!(request.contains(r#""messages":[{"role":"user","content":"Hello?"}]"#));
+    }
+
+    #[tokio::test]
+    async fn test_chat_error() {
+        let server = MockServer::start(vec![(
+            401,
+            r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#.to_string(),
+        )])
+        .await;
+        let anthropic = Anthropic::new("bad-key", DEFAULT_MODEL).with_api_base(&server.url());
+
+        let result = anthropic
+            .chat("system prompt".to_string(), "Hello?".to_string())
+            .await;
+
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("authentication_error"));
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_chat_truncated() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"content":[{"type":"text","text":"你好"}],"stop_reason":"max_tokens","usage":{"input_tokens":20,"output_tokens":4000}}"#.to_string(),
+        )])
+        .await;
+        let anthropic = Anthropic::new("test-key", DEFAULT_MODEL).with_api_base(&server.url());
+
+        let result = anthropic
+            .chat("system prompt".to_string(), "Hello?".to_string())
+            .await;
+
+        assert!(matches!(result, Err(BackendError::InvalidResponse(_))));
+    }
+}
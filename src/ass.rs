@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+use crate::subtitle_combiner::{CombinedCue, Layout};
+
+const TRANSLATED_STYLE: &str = "Translated";
+const ORIGINAL_STYLE: &str = "Original";
+/// `[V4+ Styles]` 中 `Format` 行的标准字段
+const STYLE_FORMAT: &str = "Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+
+/// ASS 中的一个样式
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssStyle {
+    pub font: String,
+    pub size: u32,
+    /// `#RRGGBB` 或 ASS 的 `&HAABBGGRR`
+    pub colour: String,
+    pub outline_colour: String,
+    pub bold: bool,
+    /// 小键盘方向的对齐方式，2 为底部居中
+    pub alignment: u8,
+    pub margin_l: u32,
+    pub margin_r: u32,
+    pub margin_v: u32,
+}
+
+/// 配置文件中的样式，未设置的字段使用默认样式
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct AssStyleSettings {
+    font: Option<String>,
+    size: Option<u32>,
+    colour: Option<String>,
+    outline_colour: Option<String>,
+    bold: Option<bool>,
+    alignment: Option<u8>,
+    margin_l: Option<u32>,
+    margin_r: Option<u32>,
+    margin_v: Option<u32>,
+}
+
+/// 配置文件中的 `[ass]` 部分
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct AssSettings {
+    translated: Option<AssStyleSettings>,
+    original: Option<AssStyleSettings>,
+}
+
+impl AssStyle {
+    fn merge(&mut self, settings: AssStyleSettings) {
+        macro_rules! merge_fields {
+            ($($field:ident),*) => {
+                $(if let Some(value) = settings.$field {
+                    self.$field = value;
+                })*
+            };
+        }
+        merge_fields!(
+            font,
+            size,
+            colour,
+            outline_colour,
+            bold,
+            alignment,
+            margin_l,
+            margin_r,
+            margin_v
+        );
+    }
+
+    /// 按 `format` 中字段的顺序生成 `Style:` 行，未知的字段填 0
+    fn to_line(&self, name: &str, format: &str) -> String {
+        let fields: Vec<String> = format
+            .split(',')
+            .map(|field| match field.trim() {
+                "Name" => name.to_string(),
+                "Fontname" => self.font.clone(),
+                "Fontsize" => self.size.to_string(),
+                "PrimaryColour" => ass_colour(&self.colour),
+                "SecondaryColour" => "&H000000FF".to_string(),
+                "OutlineColour" | "TertiaryColour" => ass_colour(&self.outline_colour),
+                "BackColour" => "&H80000000".to_string(),
+                "Bold" => if self.bold { "-1" } else { "0" }.to_string(),
+                "ScaleX" | "ScaleY" => "100".to_string(),
+                "BorderStyle" | "Shadow" | "Encoding" => "1".to_string(),
+                "Outline" => "2".to_string(),
+                "Alignment" => self.alignment.to_string(),
+                "MarginL" => self.margin_l.to_string(),
+                "MarginR" => self.margin_r.to_string(),
+                "MarginV" => self.margin_v.to_string(),
+                _ => "0".to_string(),
+            })
+            .collect();
+        format!("Style: {}", fields.join(","))
+    }
+}
+
+/// 译文和原文使用的样式
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssStyles {
+    pub translated: AssStyle,
+    pub original: AssStyle,
+}
+
+impl Default for AssStyles {
+    fn default() -> Self {
+        Self {
+            translated: AssStyle {
+                font: "Arial".to_string(),
+                size: 64,
+                colour: "#FFFFFF".to_string(),
+                outline_colour: "#000000".to_string(),
+                bold: false,
+                alignment: 2,
+                margin_l: 20,
+                margin_r: 20,
+                margin_v: 30,
+            },
+            original: AssStyle {
+                font: "Arial".to_string(),
+                size: 44,
+                colour: "#C8C8C8".to_string(),
+                outline_colour: "#000000".to_string(),
+                bold: false,
+                alignment: 2,
+                margin_l: 20,
+                margin_r: 20,
+                margin_v: 30,
+            },
+        }
+    }
+}
+
+impl AssStyles {
+    /// 用配置文件中设置的字段覆盖当前样式
+    pub fn merge(&mut self, settings: AssSettings) {
+        if let Some(translated) = settings.translated {
+            self.translated.merge(translated);
+        }
+        if let Some(original) = settings.original {
+            self.original.merge(original);
+        }
+    }
+
+    fn header(&self) -> String {
+        format!(
+            "[Script Info]
+ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+WrapStyle: 0
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: {}
+{}
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+",
+            STYLE_FORMAT,
+            self.style_lines(STYLE_FORMAT, "\n")
+        )
+    }
+
+    /// 译文和原文两个样式的 `Style:` 行
+    fn style_lines(&self, format: &str, line_ending: &str) -> String {
+        [
+            self.translated.to_line(TRANSLATED_STYLE, format),
+            self.original.to_line(ORIGINAL_STYLE, format),
+        ]
+        .join(line_ending)
+    }
+
+    /// 把译文和原文的样式加入已有 ASS 文件的样式表
+    ///
+    /// 样式按文件中 `Format` 行的字段顺序生成，同名的样式会被替换；
+    /// 文件没有样式表时在 `[Events]` 前新建一个
+    pub fn add_to(&self, content: &str) -> String {
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let line_ending = match lines.first() {
+            Some(line) if line.ends_with("\r\n") => "\r\n",
+            _ => "\n",
+        };
+        let is_section = |line: &str| line.trim_start().starts_with('[');
+        let styles_section = lines.iter().position(|line| {
+            matches!(
+                line.trim().to_ascii_lowercase().as_str(),
+                "[v4+ styles]" | "[v4 styles]"
+            )
+        });
+        let Some(start) = styles_section else {
+            let events = lines
+                .iter()
+                .position(|line| line.trim().eq_ignore_ascii_case("[events]"))
+                .unwrap_or(lines.len());
+            let section = format!(
+                "[V4+ Styles]{0}Format: {1}{0}{2}{0}{0}",
+                line_ending,
+                STYLE_FORMAT,
+                self.style_lines(STYLE_FORMAT, line_ending)
+            );
+            return [lines[..events].concat(), section, lines[events..].concat()].concat();
+        };
+        let end = lines[start + 1..]
+            .iter()
+            .position(|line| is_section(line))
+            .map_or(lines.len(), |offset| start + 1 + offset);
+        let format = lines[start + 1..end]
+            .iter()
+            .find_map(|line| line.trim().strip_prefix("Format:"))
+            .map(str::trim)
+            .unwrap_or(STYLE_FORMAT);
+        let replaced = |line: &str| {
+            line.trim()
+                .strip_prefix("Style:")
+                .and_then(|style| style.split(',').next())
+                .is_some_and(|name| [TRANSLATED_STYLE, ORIGINAL_STYLE].contains(&name.trim()))
+        };
+        // 插入到样式表最后一个非空行之后
+        let insert = lines[start..end]
+            .iter()
+            .rposition(|line| !line.trim().is_empty())
+            .map_or(end, |offset| start + offset + 1);
+        let mut content: String = lines[..insert]
+            .iter()
+            .filter(|line| !replaced(line))
+            .copied()
+            .collect();
+        if !content.ends_with('\n') {
+            content.push_str(line_ending);
+        }
+        content.push_str(&self.style_lines(format, line_ending));
+        content.push_str(line_ending);
+        content.extend(lines[insert..].iter().copied());
+        content
+    }
+}
+
+/// 在已有的 ASS 事件中排列译文和原文
+///
+/// `restyle` 为 true 时用 `\r` 覆盖标签切换译文和原文的样式，否则保留事件原来的样式；
+/// 没有译文的事件保持原样
+pub fn styled_text(cue: &CombinedCue, layout: Layout, restyle: bool) -> String {
+    let Some(translated) = &cue.translated else {
+        return cue.original.replace('\n', "\\N");
+    };
+    let style = |name: &str, text: &str| {
+        if restyle {
+            format!("{{\\r{}}}{}", name, text)
+        } else {
+            text.to_string()
+        }
+    };
+    let translated = style(TRANSLATED_STYLE, translated);
+    let original = style(ORIGINAL_STYLE, &cue.original);
+    let text = match layout {
+        Layout::TranslationOnly | Layout::Separate => translated,
+        Layout::TranslationAbove => format!("{}\n{}", translated, original),
+        Layout::OriginalAbove => format!("{}\n{}", original, translated),
+    };
+    text.replace('\n', "\\N")
+}
+
+/// 已有 ASS 文件中每个 `Dialogue` 事件的样式名，顺序与解析得到的字幕条目相同
+pub fn event_styles(content: &str) -> Vec<String> {
+    let mut in_events = false;
+    let mut style_field = 3;
+    let mut styles = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+        } else if !in_events {
+            continue;
+        } else if let Some(format) = line.strip_prefix("Format:") {
+            style_field = format
+                .split(',')
+                .position(|field| field.trim().eq_ignore_ascii_case("style"))
+                .unwrap_or(3);
+        } else if let Some(fields) = line.strip_prefix("Dialogue:") {
+            let style = fields.split(',').nth(style_field).unwrap_or_default();
+            styles.push(style.trim().to_string());
+        }
+    }
+    styles
+}
+
+/// 对白使用的样式，即被最多事件使用的样式
+///
+/// 只有对白事件换成译文和原文的样式，标题、注释等自定义样式的事件保留原样式
+pub fn dialogue_style(event_styles: &[String]) -> Option<&str> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for style in event_styles {
+        *counts.entry(style.as_str()).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(style, _)| style)
+}
+
+/// 生成完整的 ASS 文件，每条字幕的译文和原文分别作为一个 Dialogue 事件
+///
+/// 播放器会把同一时间的底部事件依次向上堆叠，先写入的在最下面，
+/// 因此按布局中从下到上的顺序写入
+pub fn render(styles: &AssStyles, cues: &[(TimeSpan, CombinedCue)], layout: Layout) -> String {
+    let mut content = styles.header();
+    for (timespan, cue) in cues {
+        let translated = cue.translated.as_ref().map(|text| (TRANSLATED_STYLE, text));
+        let original = (ORIGINAL_STYLE, &cue.original);
+        let events = match (layout, translated) {
+            (_, None) => vec![original],
+            (Layout::TranslationOnly | Layout::Separate, Some(translated)) => vec![translated],
+            (Layout::TranslationAbove, Some(translated)) => vec![original, translated],
+            (Layout::OriginalAbove, Some(translated)) => vec![translated, original],
+        };
+        for (style, text) in events {
+            content.push_str(&format!(
+                "Dialogue: 0,{},{},{},,0,0,0,,{}\n",
+                ass_time(timespan.start),
+                ass_time(timespan.end),
+                style,
+                ass_text(text)
+            ));
+        }
+    }
+    content
+}
+
+/// 把普通文本转换为 ASS 事件文本
+fn ass_text(text: &str) -> String {
+    text.replace("\\N", "\n")
+        .replace("<i>", "{\\i1}")
+        .replace("</i>", "{\\i0}")
+        .replace("<b>", "{\\b1}")
+        .replace("</b>", "{\\b0}")
+        .replace('\n', "\\N")
+}
+
+/// `#RRGGBB` 转换为 `&H00BBGGRR`，其他写法原样保留
+fn ass_colour(colour: &str) -> String {
+    match colour.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            let hex = hex.to_ascii_uppercase();
+            format!("&H00{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2])
+        }
+        _ => colour.to_string(),
+    }
+}
+
+/// ASS 时间格式 `H:MM:SS.cc`
+fn ass_time(t: TimePoint) -> String {
+    let t = if t.msecs() < 0 {
+        TimePoint::from_msecs(0)
+    } else {
+        t
+    };
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        t.hours(),
+        t.mins_comp(),
+        t.secs_comp(),
+        t.msecs_comp() / 10
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cues() -> Vec<(TimeSpan, CombinedCue)> {
+        vec![(
+            TimeSpan::new(TimePoint::from_msecs(94095), TimePoint::from_msecs(96180)),
+            CombinedCue {
+                translated: Some("你的魔法师到底在哪？".to_string()),
+                original: "Where in hell is\nyour so-called <i>magician</i>?".to_string(),
+            },
+        )]
+    }
+
+    #[test]
+    fn test_styles() {
+        let mut styles = AssStyles::default();
+        let settings: AssSettings =
+            toml::from_str("[translated]\nfont = \"Noto Sans CJK SC\"\ncolour = \"#ffcc00\"\n")
+                .unwrap();
+        styles.merge(settings);
+
+        let content = render(&styles, &[], Layout::TranslationAbove);
+
+        assert!(content.contains(
+            "Style: Translated,Noto Sans CJK SC,64,&H0000CCFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,1,2,20,20,30,1\n"
+        ));
+        assert!(content.contains("Style: Original,Arial,44,&H00C8C8C8,"));
+    }
+
+    #[test]
+    fn test_event_order() {
+        let styles = AssStyles::default();
+
+        let above = render(&styles, &cues(), Layout::TranslationAbove);
+        let below = render(&styles, &cues(), Layout::OriginalAbove);
+        let only = render(&styles, &cues(), Layout::TranslationOnly);
+
+        let original = "Dialogue: 0,0:01:34.09,0:01:36.18,Original,,0,0,0,,Where in hell is\\Nyour so-called {\\i1}magician{\\i0}?\n";
+        let translated =
+            "Dialogue: 0,0:01:34.09,0:01:36.18,Translated,,0,0,0,,你的魔法师到底在哪？\n";
+        assert!(above.ends_with(&format!("{}{}", original, translated)));
+        assert!(below.ends_with(&format!("{}{}", translated, original)));
+        assert!(only.ends_with(&format!(
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n{}",
+            translated
+        )));
+    }
+
+    #[test]
+    fn test_styled_text() {
+        let untranslated = CombinedCue {
+            translated: None,
+            original: "Where in hell is\nyour magician?".to_string(),
+        };
+        let translated = CombinedCue {
+            translated: Some("他在哪？".to_string()),
+            original: "Where is he?".to_string(),
+        };
+
+        assert_eq!(
+            styled_text(&untranslated, Layout::TranslationAbove, true),
+            "Where in hell is\\Nyour magician?"
+        );
+        assert_eq!(
+            styled_text(&translated, Layout::TranslationAbove, true),
+            "{\\rTranslated}他在哪？\\N{\\rOriginal}Where is he?"
+        );
+        assert_eq!(
+            styled_text(&translated, Layout::TranslationAbove, false),
+            "他在哪？\\NWhere is he?"
+        );
+    }
+
+    #[test]
+    fn test_event_styles() {
+        let content = std::fs::read_to_string("test.ass").unwrap();
+
+        let styles = event_styles(&content);
+
+        assert_eq!(styles, ["Default", "Default", "Sign"]);
+        assert_eq!(dialogue_style(&styles), Some("Default"));
+    }
+}
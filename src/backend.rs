@@ -0,0 +1,288 @@
+use async_trait::async_trait;
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{ops::AddAssign, sync::Arc, time::Duration};
+use thiserror::Error;
+
+use crate::{
+    anthropic::{self, Anthropic},
+    config::{Config, Provider},
+    echo::EchoBackend,
+    error::StcError,
+    local::{LocalLlm, LocalServer},
+    openai::{self, OpenAI},
+    retry,
+};
+
+#[derive(Error, Debug)]
+pub enum BackendError {
+    /// 连接失败、超时等网络错误
+    #[error("Request failed: {0}")]
+    RequestError(String),
+    /// 服务端返回的错误状态码
+    #[error("Request failed: {status} {message}")]
+    HttpError {
+        status: StatusCode,
+        message: String,
+        /// 服务端要求的等待时间，来自 `Retry-After` 或限流响应头
+        retry_after: Option<Duration>,
+    },
+    /// 请求本身无效或额度已用完，重试不会成功
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+}
+
+impl BackendError {
+    /// 由错误响应的状态码和响应头构造
+    pub fn http(status: StatusCode, headers: &HeaderMap, message: String) -> Self {
+        BackendError::HttpError {
+            status,
+            message,
+            retry_after: retry::retry_after(headers),
+        }
+    }
+
+    /// 网络错误、限流和服务端错误可以重试，其他错误直接失败
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BackendError::RequestError(_) => true,
+            BackendError::HttpError { status, .. } => {
+                matches!(status.as_u16(), 408 | 409 | 425 | 429)
+                    || (status.is_server_error() && *status != StatusCode::NOT_IMPLEMENTED)
+            }
+            BackendError::InvalidRequest(_) | BackendError::InvalidResponse(_) => false,
+        }
+    }
+
+    /// 是否因为限流而失败
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, BackendError::HttpError { status, .. } if *status == StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BackendError::HttpError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// 一次请求消耗的 token 数量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// 翻译后端返回的结果
+#[derive(Debug, Clone, Default)]
+pub struct BackendResponse {
+    pub text: String,
+    pub usage: Usage,
+}
+
+/// 翻译后端，每个模型提供商实现一次
+///
+/// `prompt` 是系统提示，`user_message` 是包含待翻译文本的用户消息
+#[async_trait]
+pub trait TranslationBackend: Send + Sync + std::fmt::Debug {
+    /// 后端名称，如 `openai`
+    fn name(&self) -> &str;
+
+    /// 使用的模型名称
+    fn model(&self) -> &str;
+
+    /// 影响回复内容的请求参数，如 temperature，作为缓存 key 的一部分
+    fn parameters(&self) -> String {
+        String::new()
+    }
+
+    async fn chat(
+        &self,
+        prompt: String,
+        user_message: String,
+    ) -> Result<BackendResponse, BackendError>;
+
+    /// 要求模型返回符合 `schema` 的 JSON 对象
+    ///
+    /// 默认忽略 `schema` 直接调用 [`chat`](Self::chat)，由调用方从回复文本中提取 JSON
+    async fn chat_json(
+        &self,
+        prompt: String,
+        user_message: String,
+        schema: serde_json::Value,
+    ) -> Result<BackendResponse, BackendError> {
+        let _ = schema;
+        self.chat(prompt, user_message).await
+    }
+
+    /// 发送一个分块的翻译请求，`schema` 不为 `None` 时要求返回 JSON
+    ///
+    /// `chunk` 是放进用户消息的原文，即 `<T>` 分隔的文本或编号的 JSON 对象。
+    /// 默认调用 [`chat`](Self::chat) 或 [`chat_json`](Self::chat_json)，
+    /// 离线后端可以直接处理 `chunk`，不依赖模板的内容
+    async fn chat_chunk(
+        &self,
+        prompt: String,
+        user_message: String,
+        chunk: &str,
+        schema: Option<serde_json::Value>,
+    ) -> Result<BackendResponse, BackendError> {
+        let _ = chunk;
+        match schema {
+            Some(schema) => self.chat_json(prompt, user_message, schema).await,
+            None => self.chat(prompt, user_message).await,
+        }
+    }
+}
+
+/// 根据配置中的 provider 创建翻译后端
+///
+/// 没有为当前 provider 设置的 `api_base` 和 `model` 使用各提供商的默认值，
+/// 参见 [`Config::is_set_for_provider`]
+pub fn create_backend(config: &Config) -> crate::error::Result<Arc<dyn TranslationBackend>> {
+    let model = |default: &'static str| match config.is_set_for_provider("model") {
+        true => config.model.as_str(),
+        false => default,
+    };
+    let api_base = config
+        .is_set_for_provider("api_base")
+        .then_some(config.api_base.as_str());
+    let backend: Arc<dyn TranslationBackend> = match config.provider {
+        Provider::OpenAI => {
+            let backend = OpenAI::new(config.require_api_key()?, model(openai::DEFAULT_MODEL))
+                .with_api_base(api_base.unwrap_or(openai::DEFAULT_API_BASE))
+                .with_temperature(config.temperature);
+            Arc::new(backend)
+        }
+        Provider::Anthropic => {
+            let mut backend =
+                Anthropic::new(config.require_api_key()?, model(anthropic::DEFAULT_MODEL))
+                    .with_temperature(config.temperature);
+            if let Some(api_base) = api_base {
+                backend = backend.with_api_base(api_base);
+            }
+            Arc::new(backend)
+        }
+        Provider::Ollama | Provider::LlamaCpp => {
+            if !config.is_set_for_provider("model") {
+                return Err(StcError::ConfigError(format!(
+                    "本地模型需要通过 --model、STC_MODEL 或 provider 为 {} 的配置指定",
+                    config.provider
+                )));
+            }
+            let server = match config.provider {
+                Provider::Ollama => LocalServer::Ollama,
+                _ => LocalServer::LlamaCpp,
+            };
+            let mut backend = LocalLlm::new(server, &config.model)
+                .with_temperature(config.temperature)
+                .with_context_size(config.context_size)
+                .with_keep_alive(config.keep_alive.clone());
+            if let Some(api_base) = api_base {
+                backend = backend.with_api_base(api_base);
+            }
+            Arc::new(backend)
+        }
+        Provider::Echo => Arc::new(EchoBackend::new()),
+    };
+    Ok(backend)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ConfigOverrides;
+
+    fn load(files: &[std::path::PathBuf], provider: Provider) -> Config {
+        let overrides = ConfigOverrides {
+            provider: Some(provider),
+            ..Default::default()
+        };
+        Config::load_with(
+            files,
+            |name| (name == "ANTHROPIC_API_KEY").then(|| "sk-ant-key".to_string()),
+            &overrides,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_openai_settings_not_used_for_anthropic() {
+        let dir = crate::mock::temp_dir("backend_anthropic");
+        let path = dir.join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"api_key": "sk-openai", "api_base": "https://api.openai.com/v1", "model": "gpt-4o-mini"}"#,
+        )
+        .unwrap();
+
+        let backend = create_backend(&load(&[path], Provider::Anthropic)).unwrap();
+
+        assert_eq!(backend.model(), anthropic::DEFAULT_MODEL);
+        let debug = format!("{:?}", backend);
+        assert!(debug.contains("https://api.anthropic.com"));
+        assert!(debug.contains("sk-ant-key"));
+    }
+
+    #[test]
+    fn test_openai_settings_not_used_for_local() {
+        let dir = crate::mock::temp_dir("backend_local");
+        let path = dir.join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"api_key": "sk-openai", "api_base": "https://api.openai.com/v1", "model": "gpt-4o-mini"}"#,
+        )
+        .unwrap();
+        let overrides = ConfigOverrides {
+            provider: Some(Provider::Ollama),
+            model: Some("qwen2.5:7b".to_string()),
+            ..Default::default()
+        };
+
+        let missing_model =
+            create_backend(&load(std::slice::from_ref(&path), Provider::Ollama)).unwrap_err();
+        let backend =
+            create_backend(&Config::load_with(&[path], |_| None, &overrides).unwrap()).unwrap();
+
+        assert!(missing_model.to_string().contains("--model"));
+        assert_eq!(backend.model(), "qwen2.5:7b");
+        let debug = format!("{:?}", backend);
+        assert!(debug.contains(crate::local::DEFAULT_OLLAMA_URL));
+        assert!(!debug.contains("api.openai.com"));
+    }
+
+    #[test]
+    fn test_provider_profile_settings() {
+        let dir = crate::mock::temp_dir("backend_profile");
+        let path = dir.join("config.toml");
+        std::fs::write(
+            &path,
+            "model = \"gpt-4o\"\ndefault_profile = \"claude\"\n\n[profiles.claude]\nprovider = \"anthropic\"\nmodel = \"claude-3-5-haiku-latest\"\napi_base = \"https://proxy.example\"\n",
+        )
+        .unwrap();
+
+        let anthropic = create_backend(&load(std::slice::from_ref(&path), Provider::Anthropic));
+        let openai = load(&[path], Provider::OpenAI);
+
+        let anthropic = anthropic.unwrap();
+        assert_eq!(anthropic.model(), "claude-3-5-haiku-latest");
+        assert!(format!("{:?}", anthropic).contains("https://proxy.example"));
+        // profile 中为 anthropic 设置的 model 不用于 openai
+        assert!(!openai.is_set_for_provider("model"));
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use crate::{checkpoint::hash, error::Result};
+
+/// 按内容寻址的翻译缓存，每个回复保存为 `<dir>/<key 前两位>/<key>.txt`
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+/// 缓存目录的统计信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/stc`，未设置时为 `$HOME/.cache/stc`
+    pub fn user_cache_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|dir| dir.join("stc"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 由请求的所有组成部分计算缓存的 key
+    pub fn key(parts: &[&str]) -> String {
+        // 两个方向各算一次，拼成 128 位，降低碰撞的概率
+        let reversed: Vec<&str> = parts.iter().rev().copied().collect();
+        format!("{}{}", hash(parts), hash(&reversed))
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{}.txt", key))
+    }
+
+    /// 读取缓存的回复，同时记录命中次数
+    pub fn get(&self, key: &str) -> Option<String> {
+        match std::fs::read_to_string(self.entry_path(key)) {
+            Ok(text) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(text)
+            }
+            Err(_) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// 写入回复，先写临时文件再重命名，并发写入同一个 key 也不会产生不完整的文件
+    pub fn put(&self, key: &str, text: &str) -> Result<()> {
+        // 同一进程内的每次写入使用不同的临时文件
+        static WRITES: AtomicUsize = AtomicUsize::new(0);
+        let path = self.entry_path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// 本次运行命中缓存的次数
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// 本次运行未命中缓存的次数
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, std::fs::Metadata)>> {
+        let mut entries = Vec::new();
+        let Ok(dirs) = std::fs::read_dir(&self.dir) else {
+            return Ok(entries);
+        };
+        for dir in dirs {
+            let dir = dir?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.is_file() {
+                    entries.push((entry.path(), metadata));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// 统计缓存的条目数量和占用空间
+    pub fn stats(&self) -> Result<CacheStats> {
+        Ok(self
+            .entries()?
+            .iter()
+            .fold(CacheStats::default(), |stats, (_, metadata)| CacheStats {
+                entries: stats.entries + 1,
+                bytes: stats.bytes + metadata.len(),
+            }))
+    }
+
+    /// 删除超过 `older_than` 未更新的条目，为 `None` 时清空缓存，返回删除的数量
+    pub fn prune(&self, older_than: Option<Duration>) -> Result<usize> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for (path, metadata) in self.entries()? {
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            if older_than.is_none_or(|limit| age >= limit) {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let cache = Cache::new(crate::mock::temp_dir("cache"));
+        let key = Cache::key(&["prompt", "user message", "gpt-4o-mini"]);
+
+        assert_eq!(key.len(), 32);
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, "```\n译文\n```").unwrap();
+        assert_eq!(cache.get(&key).as_deref(), Some("```\n译文\n```"));
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.stats().unwrap().entries, 1);
+
+        assert_eq!(cache.prune(Some(Duration::from_secs(3600))).unwrap(), 0);
+        assert_eq!(cache.prune(None).unwrap(), 1);
+        assert_eq!(cache.stats().unwrap(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_concurrent_put() {
+        let cache = Cache::new(crate::mock::temp_dir("cache_concurrent"));
+        let key = Cache::key(&["prompt"]);
+
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let (cache, key) = (&cache, &key);
+                scope.spawn(move || cache.put(key, &format!("译文 {}", i)).unwrap());
+            }
+        });
+
+        assert!(cache.get(&key).unwrap().starts_with("译文 "));
+        assert_eq!(cache.stats().unwrap().entries, 1);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// 计算若干段文本的 64 位 FNV-1a 哈希，结果在不同平台和版本间保持一致
+pub fn hash(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        // 每段后加入分隔字节，避免 ["ab", "c"] 和 ["a", "bc"] 相同
+        for byte in part.bytes().chain([0xff]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// 翻译的检查点，每完成一个分块就写入文件，中断后重新运行时跳过已完成的分块
+///
+/// `key` 由输入文本、语言、模型和 prompt 计算，任意一项变化时旧的检查点失效
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Checkpoint {
+    key: String,
+    chunks: BTreeMap<usize, String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Checkpoint {
+    /// 输出文件对应的检查点文件 `<output>.checkpoint.json`
+    pub fn path_for(output_path: &Path) -> PathBuf {
+        let mut name = output_path.as_os_str().to_owned();
+        name.push(".checkpoint.json");
+        PathBuf::from(name)
+    }
+
+    /// 读取检查点
+    ///
+    /// 文件不存在、无法解析、`key` 不一致或 `resume` 为 `false` 时从空的检查点开始
+    pub fn load(path: PathBuf, key: String, resume: bool) -> Self {
+        let previous = std::fs::read_to_string(&path)
+            .ok()
+            .filter(|_| resume)
+            .and_then(|content| serde_json::from_str::<Checkpoint>(&content).ok())
+            .filter(|checkpoint| checkpoint.key == key);
+        Self {
+            key,
+            chunks: previous.map(|c| c.chunks).unwrap_or_default(),
+            path,
+        }
+    }
+
+    /// 已完成分块的译文
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.chunks.get(&index).map(String::as_str)
+    }
+
+    /// 已完成的分块数量
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// 记录一个完成的分块并立即写入文件
+    ///
+    /// 先写入临时文件再重命名，避免中断时留下不完整的检查点
+    pub fn save_chunk(&mut self, index: usize, translated_text: &str) -> Result<()> {
+        self.chunks.insert(index, translated_text.to_string());
+        let content = serde_json::to_string(self).map_err(std::io::Error::from)?;
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// 翻译完成后删除检查点文件
+    pub fn remove(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash(&[]), "cbf29ce484222325");
+        assert_eq!(hash(&["ab", "c"]), hash(&["ab", "c"]));
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = crate::mock::temp_dir("checkpoint");
+        let path = Checkpoint::path_for(&dir.join("test.zh_CN.srt"));
+
+        let mut checkpoint = Checkpoint::load(path.clone(), "key".to_string(), true);
+        checkpoint.save_chunk(2, "译文").unwrap();
+
+        let resumed = Checkpoint::load(path.clone(), "key".to_string(), true);
+        assert_eq!(resumed.get(2), Some("译文"));
+        assert!(Checkpoint::load(path.clone(), "other".to_string(), true).is_empty());
+        assert!(Checkpoint::load(path.clone(), "key".to_string(), false).is_empty());
+
+        Checkpoint::remove(&path).unwrap();
+        assert!(!path.exists());
+        Checkpoint::remove(&path).unwrap();
+    }
+}
@@ -1,28 +1,973 @@
-use std::{error::Error, fs::File, io::BufReader, path::Path};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
+use crate::{
+    ass::{AssSettings, AssStyles},
+    error::{Result, StcError},
+    formatter::ContextWindow,
+    openai::{DEFAULT_API_BASE, DEFAULT_MODEL},
+    retry::DEFAULT_MAX_ATTEMPTS,
+    structured::ResponseMode,
+    subtitle_combiner::Layout,
+    text_splitter::{
+        ChunkSettings, DEFAULT_CHUNK_TOKENS, DEFAULT_MAX_CUES, DEFAULT_MIN_CUES,
+        DEFAULT_SCENE_GAP_MS,
+    },
+    wrap::{WrapSettings, DEFAULT_MAX_LINES},
+    writer::OutputFormat,
+};
+
+const DEFAULT_CONCURRENCY: usize = 10;
+/// 项目目录下的配置文件，后面的优先级更高
+const LOCAL_CONFIG_FILES: [&str; 2] = ["config.json", "config.toml"];
+
+/// 模型提供商
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    #[serde(rename = "openai")]
+    #[value(name = "openai")]
+    OpenAI,
+    Anthropic,
+    Ollama,
+    LlamaCpp,
+    Echo,
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Provider::OpenAI => "openai",
+            Provider::Anthropic => "anthropic",
+            Provider::Ollama => "ollama",
+            Provider::LlamaCpp => "llama-cpp",
+            Provider::Echo => "echo",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Provider {
+    /// 未设置 `api_key_env` 时读取密钥的环境变量
+    pub fn default_api_key_env(&self) -> Option<&'static str> {
+        match self {
+            Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            _ => None,
+        }
+    }
+}
+
+/// 配置项的来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Profile(String),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "file: {}", path.display()),
+            ConfigSource::Profile(name) => write!(f, "profile: {}", name),
+            ConfigSource::Env(name) => write!(f, "env: {}", name),
+            ConfigSource::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// 命令行传入的配置覆盖项
+#[derive(Debug, Default, Clone)]
+pub struct ConfigOverrides {
+    pub config_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub provider: Option<Provider>,
+    pub api_base: Option<String>,
+    pub model: Option<String>,
+    pub context_size: Option<u32>,
+    pub keep_alive: Option<String>,
+    pub template_dir: Option<PathBuf>,
+    pub output_format: Option<OutputFormat>,
+    pub layout: Option<Layout>,
+    pub resume: Option<bool>,
+    pub cache: Option<bool>,
+    pub max_attempts: Option<u32>,
+    pub response_mode: Option<ResponseMode>,
+    pub chunk_tokens: Option<usize>,
+    pub min_cues: Option<usize>,
+    pub max_cues: Option<usize>,
+    pub scene_gap_ms: Option<u64>,
+    pub max_line_width: Option<usize>,
+    pub max_lines: Option<usize>,
+    pub context_window: Option<ContextWindow>,
+    pub merge_sentences: Option<bool>,
+    pub glossary: Option<PathBuf>,
+}
+
+/// 一组可选的配置项，配置文件顶层和每个 profile 都使用这个结构
+#[derive(Deserialize, Debug, Default, Clone)]
+struct Settings {
+    provider: Option<Provider>,
+    api_key: Option<String>,
+    /// 从该环境变量读取 api_key
+    api_key_env: Option<String>,
+    api_base: Option<String>,
+    model: Option<String>,
+    temperature: Option<f32>,
+    concurrency: Option<usize>,
+    context_size: Option<u32>,
+    keep_alive: Option<String>,
+    template_dir: Option<PathBuf>,
+    output_format: Option<OutputFormat>,
+    layout: Option<Layout>,
+    /// 新建 ASS 文件时使用的样式
+    ass: Option<AssSettings>,
+    resume: Option<bool>,
+    cache: Option<bool>,
+    cache_dir: Option<PathBuf>,
+    max_attempts: Option<u32>,
+    response_mode: Option<ResponseMode>,
+    chunk_tokens: Option<usize>,
+    min_cues: Option<usize>,
+    max_cues: Option<usize>,
+    scene_gap_ms: Option<u64>,
+    max_line_width: Option<usize>,
+    max_lines: Option<usize>,
+    context_window: Option<ContextWindow>,
+    merge_sentences: Option<bool>,
+    glossary: Option<PathBuf>,
+}
+
+impl Settings {
+    /// 用 `other` 中已设置的字段覆盖当前字段
+    fn merge(&mut self, other: Settings) {
+        macro_rules! merge_fields {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+        merge_fields!(
+            provider,
+            api_key,
+            api_key_env,
+            api_base,
+            model,
+            temperature,
+            concurrency,
+            context_size,
+            keep_alive,
+            template_dir,
+            output_format,
+            layout,
+            ass,
+            resume,
+            cache,
+            cache_dir,
+            max_attempts,
+            response_mode,
+            chunk_tokens,
+            min_cues,
+            max_cues,
+            scene_gap_ms,
+            max_line_width,
+            max_lines,
+            context_window,
+            merge_sentences,
+            glossary
+        );
+    }
+
+    /// 由环境变量 `name` 的值构造只设置了 `key` 的配置层
+    ///
+    /// 值先按字符串解析，失败时再按 JSON 解析，以支持数字和布尔值
+    fn from_env(name: &str, key: &str, value: String) -> Result<Settings> {
+        let parse = |value: serde_json::Value| {
+            serde_json::from_value(serde_json::json!({ key: value })).ok()
+        };
+        parse(serde_json::Value::String(value.clone()))
+            .or_else(|| serde_json::from_str(&value).ok().and_then(parse))
+            .ok_or_else(|| StcError::ConfigError(format!("环境变量 {} 的值无效: {}", name, value)))
+    }
+}
+
+/// 可以通过 `STC_<KEY>` 环境变量设置的配置项，`api_key` 在其他项之后单独读取
+const ENV_KEYS: [&str; 25] = [
+    "provider",
+    "api_key_env",
+    "api_base",
+    "model",
+    "temperature",
+    "concurrency",
+    "context_size",
+    "keep_alive",
+    "template_dir",
+    "output_format",
+    "layout",
+    "resume",
+    "cache",
+    "cache_dir",
+    "max_attempts",
+    "response_mode",
+    "chunk_tokens",
+    "min_cues",
+    "max_cues",
+    "scene_gap_ms",
+    "max_line_width",
+    "max_lines",
+    "context_window",
+    "merge_sentences",
+    "glossary",
+];
+
+/// 单个配置文件中的内容
+#[derive(Deserialize, Debug, Default)]
+struct ConfigFile {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Settings>,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+impl ConfigFile {
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            StcError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        let file = match path.extension().and_then(|s| s.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+            _ => serde_json::from_str(&content).map_err(|e| e.to_string()),
+        };
+        file.map_err(|e| {
+            StcError::ConfigError(format!("Failed to parse {}: {}", path.display(), e))
+        })
+    }
+}
+
+/// 分层配置，优先级从低到高：
+/// 内置默认值、`$XDG_CONFIG_HOME/stc/config.{json,toml}`、
+/// 当前目录下的 `config.{json,toml}`、`--config` 指定的文件、选中的 profile、
+/// `STC_*` 环境变量、命令行参数
+#[derive(Debug, Clone)]
 pub struct Config {
+    pub provider: Provider,
     pub api_key: String,
+    pub api_key_env: Option<String>,
     pub api_base: String,
     pub model: String,
+    pub temperature: Option<f32>,
+    pub concurrency: usize,
+    pub context_size: Option<u32>,
+    pub keep_alive: Option<String>,
+    /// 覆盖内置模板的目录，默认为 `$XDG_CONFIG_HOME/stc/templates`
+    pub template_dir: Option<PathBuf>,
+    /// 输出的字幕格式，未设置时与输入文件相同
+    pub output_format: Option<OutputFormat>,
+    /// 双语字幕的排列方式
+    pub layout: Layout,
+    /// 新建 ASS 文件时译文和原文使用的样式
+    pub ass_styles: AssStyles,
+    /// 是否从上次中断的检查点继续翻译
+    pub resume: bool,
+    /// 是否使用持久化的翻译缓存
+    pub cache: bool,
+    /// 缓存目录，默认为 `$XDG_CACHE_HOME/stc`
+    pub cache_dir: Option<PathBuf>,
+    /// 每个请求最多尝试的次数，包含第一次请求
+    pub max_attempts: u32,
+    /// 要求模型返回译文的方式
+    pub response_mode: ResponseMode,
+    /// 每个分块中原文的估算 token 上限
+    pub chunk_tokens: usize,
+    /// 每个分块至少包含的字幕数量
+    pub min_cues: usize,
+    /// 每个分块最多包含的字幕数量
+    pub max_cues: usize,
+    /// 达到一半容量的分块遇到超过该毫秒数的静默时提前结束
+    pub scene_gap_ms: u64,
+    /// 译文每行的最大显示宽度，中日韩文字按 2 计算，未设置时不重新换行
+    pub max_line_width: Option<usize>,
+    /// 重新换行时每帧译文最多的行数
+    pub max_lines: usize,
+    /// 每次请求在待翻译部分前后附带的上下文
+    pub context_window: ContextWindow,
+    /// 是否把跨越多帧字幕的句子合并后整句翻译
+    pub merge_sentences: bool,
+    /// 术语表文件（.csv、.tsv 或 .json）
+    pub glossary: Option<PathBuf>,
+    /// 当前使用的 profile
+    pub profile: Option<String>,
+    /// 配置文件中定义的所有 profile
+    pub profiles: Vec<String>,
+    sources: BTreeMap<&'static str, ConfigSource>,
+    /// 设置了 provider 的配置层和它设置的 provider
+    layer_providers: Vec<(ConfigSource, Provider)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            provider: Provider::OpenAI,
+            api_key: String::new(),
+            api_key_env: None,
+            api_base: DEFAULT_API_BASE.to_string(),
+            model: DEFAULT_MODEL.to_string(),
+            temperature: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            context_size: None,
+            keep_alive: None,
+            template_dir: None,
+            output_format: None,
+            layout: Layout::default(),
+            ass_styles: AssStyles::default(),
+            resume: true,
+            cache: true,
+            cache_dir: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            response_mode: ResponseMode::default(),
+            chunk_tokens: DEFAULT_CHUNK_TOKENS,
+            min_cues: DEFAULT_MIN_CUES,
+            max_cues: DEFAULT_MAX_CUES,
+            scene_gap_ms: DEFAULT_SCENE_GAP_MS,
+            max_line_width: None,
+            max_lines: DEFAULT_MAX_LINES,
+            context_window: ContextWindow::default(),
+            merge_sentences: false,
+            glossary: None,
+            profile: None,
+            profiles: Vec::new(),
+            sources: BTreeMap::new(),
+            layer_providers: Vec::new(),
+        }
+    }
 }
 
 impl Config {
-    pub fn init() -> Result<(), Box<dyn Error>> {
-        if !Path::new("./config.json").exists() {
-            eprintln!("current dir: {:?}", std::env::current_dir());
-            return Err("配置文件 config.json 不存在".into());
-        }
-        // 尝试读取配置文件来验证其内容
-        Config::read_config_from_file("config.json")?;
-        Ok(())
-    }
-    pub fn read_config_from_file(file_path: &str) -> Result<Config, Box<dyn Error>> {
-        let file = File::open(file_path).expect("Failed to open file");
-        let reader = BufReader::new(file);
-        let config = serde_json::from_reader(reader)?;
+    /// 按优先级加载所有配置层
+    pub fn load(overrides: &ConfigOverrides) -> Result<Config> {
+        let mut files: Vec<PathBuf> = Self::user_config_dir()
+            .map(|dir| {
+                LOCAL_CONFIG_FILES
+                    .iter()
+                    .map(|name| dir.join(name))
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.extend(LOCAL_CONFIG_FILES.iter().map(PathBuf::from));
+        let files: Vec<PathBuf> = files.into_iter().filter(|path| path.exists()).collect();
+        let mut config = Self::load_with(&files, |name| std::env::var(name).ok(), overrides)?;
+        if config.template_dir.is_none() {
+            config.template_dir = Self::user_config_dir()
+                .map(|dir| dir.join("templates"))
+                .filter(|dir| dir.is_dir());
+        }
+        if config.cache_dir.is_none() {
+            config.cache_dir = crate::cache::Cache::user_cache_dir();
+        }
+        Ok(config)
+    }
+
+    /// `$XDG_CONFIG_HOME/stc`，未设置时为 `$HOME/.config/stc`
+    pub fn user_config_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("stc"))
+    }
+
+    /// 从给定的配置文件和环境变量加载配置，`env` 返回环境变量的值
+    pub(crate) fn load_with(
+        files: &[PathBuf],
+        env: impl Fn(&str) -> Option<String>,
+        overrides: &ConfigOverrides,
+    ) -> Result<Config> {
+        let mut config = Config::default();
+        let mut default_profile = None;
+        let mut profiles: BTreeMap<String, Settings> = BTreeMap::new();
+
+        let explicit = overrides.config_path.iter();
+        for path in files.iter().chain(explicit) {
+            let file = ConfigFile::from_file(path)?;
+            config.apply(file.settings, ConfigSource::File(path.clone()));
+            default_profile = file.default_profile.or(default_profile);
+            for (name, settings) in file.profiles {
+                profiles.entry(name).or_default().merge(settings);
+            }
+        }
+        config.profiles = profiles.keys().cloned().collect();
+
+        let profile = overrides
+            .profile
+            .clone()
+            .or_else(|| env("STC_PROFILE").filter(|v| !v.is_empty()))
+            .or(default_profile);
+        if let Some(name) = profile {
+            let settings = profiles.remove(&name).ok_or_else(|| {
+                StcError::ConfigError(format!(
+                    "未找到 profile {}，可用的 profile: {}",
+                    name,
+                    config.profiles.join(", ")
+                ))
+            })?;
+            config.apply(settings, ConfigSource::Profile(name.clone()));
+            config.profile = Some(name);
+        }
+
+        for key in ENV_KEYS {
+            let name = format!("STC_{}", key.to_uppercase());
+            if let Some(value) = env(&name).filter(|v| !v.is_empty()) {
+                let settings = Settings::from_env(&name, key, value)?;
+                config.apply(settings, ConfigSource::Env(name));
+            }
+        }
+
+        // 命令行可能还会改变 provider，按最终的 provider 选择默认的环境变量
+        let provider = overrides.provider.unwrap_or(config.provider);
+        let key_env = config
+            .api_key_env
+            .clone()
+            .or_else(|| provider.default_api_key_env().map(String::from));
+        if let Some(name) = key_env {
+            match env(&name).filter(|v| !v.is_empty()) {
+                Some(value) => {
+                    config.api_key = value;
+                    config.sources.insert("api_key", ConfigSource::Env(name));
+                }
+                // 其他层的 api_key 可能属于另一个提供商，不能代替未设置的环境变量
+                None if config.api_key_env.is_some()
+                    && config.source("api_key") != config.source("api_key_env") =>
+                {
+                    config.api_key.clear();
+                    config.sources.remove("api_key");
+                }
+                None => {}
+            }
+        }
+
+        if let Some(value) = env("STC_API_KEY").filter(|v| !v.is_empty()) {
+            config.apply(
+                Settings {
+                    api_key: Some(value),
+                    ..Default::default()
+                },
+                ConfigSource::Env("STC_API_KEY".to_string()),
+            );
+        }
+
+        config.apply(
+            Settings {
+                provider: overrides.provider,
+                api_base: overrides.api_base.clone(),
+                model: overrides.model.clone(),
+                context_size: overrides.context_size,
+                keep_alive: overrides.keep_alive.clone(),
+                template_dir: overrides.template_dir.clone(),
+                output_format: overrides.output_format,
+                layout: overrides.layout,
+                resume: overrides.resume,
+                cache: overrides.cache,
+                max_attempts: overrides.max_attempts,
+                response_mode: overrides.response_mode,
+                chunk_tokens: overrides.chunk_tokens,
+                min_cues: overrides.min_cues,
+                max_cues: overrides.max_cues,
+                scene_gap_ms: overrides.scene_gap_ms,
+                max_line_width: overrides.max_line_width,
+                max_lines: overrides.max_lines,
+                context_window: overrides.context_window,
+                merge_sentences: overrides.merge_sentences,
+                glossary: overrides.glossary.clone(),
+                ..Default::default()
+            },
+            ConfigSource::Cli,
+        );
+
+        if config.concurrency == 0 {
+            return Err(StcError::ConfigError("concurrency 必须大于 0".to_string()));
+        }
+        if config.max_attempts == 0 {
+            return Err(StcError::ConfigError("max_attempts 必须大于 0".to_string()));
+        }
+        if config.chunk_tokens == 0 || config.min_cues == 0 {
+            return Err(StcError::ConfigError(
+                "chunk_tokens 和 min_cues 必须大于 0".to_string(),
+            ));
+        }
+        if config.max_line_width == Some(0) || config.max_lines == 0 {
+            return Err(StcError::ConfigError(
+                "max_line_width 和 max_lines 必须大于 0".to_string(),
+            ));
+        }
+        if config.max_cues < config.min_cues {
+            return Err(StcError::ConfigError(
+                "max_cues 不能小于 min_cues".to_string(),
+            ));
+        }
         Ok(config)
     }
+
+    fn apply(&mut self, settings: Settings, source: ConfigSource) {
+        if let Some(provider) = settings.provider {
+            self.layer_providers.push((source.clone(), provider));
+        }
+        macro_rules! apply_fields {
+            ($($field:ident => $value:expr),*) => {
+                $(if let Some(value) = settings.$field {
+                    self.$field = $value(value);
+                    self.sources.insert(stringify!($field), source.clone());
+                })*
+            };
+        }
+        apply_fields!(
+            provider => std::convert::identity,
+            api_key => std::convert::identity,
+            api_key_env => Some,
+            api_base => std::convert::identity,
+            model => std::convert::identity,
+            temperature => Some,
+            concurrency => std::convert::identity,
+            context_size => Some,
+            keep_alive => Some,
+            template_dir => Some,
+            output_format => Some,
+            layout => std::convert::identity,
+            resume => std::convert::identity,
+            cache => std::convert::identity,
+            cache_dir => Some,
+            max_attempts => std::convert::identity,
+            response_mode => std::convert::identity,
+            chunk_tokens => std::convert::identity,
+            min_cues => std::convert::identity,
+            max_cues => std::convert::identity,
+            scene_gap_ms => std::convert::identity,
+            max_line_width => Some,
+            max_lines => std::convert::identity,
+            context_window => std::convert::identity,
+            merge_sentences => std::convert::identity,
+            glossary => Some
+        );
+        if let Some(ass) = settings.ass {
+            self.ass_styles.merge(ass);
+            self.sources.insert("ass_styles", source);
+        }
+    }
+
+    /// 分块的大小限制
+    pub fn chunk_settings(&self) -> ChunkSettings {
+        ChunkSettings {
+            max_tokens: self.chunk_tokens,
+            min_cues: self.min_cues,
+            max_cues: self.max_cues,
+            scene_gap_ms: self.scene_gap_ms,
+        }
+    }
+
+    /// 译文的换行规则，未设置 `max_line_width` 时为 `None`
+    pub fn wrap_settings(&self) -> Option<WrapSettings> {
+        self.max_line_width.map(|max_width| WrapSettings {
+            max_width,
+            max_lines: self.max_lines,
+        })
+    }
+
+    /// 获取配置项的来源
+    pub fn source(&self, key: &str) -> ConfigSource {
+        self.sources
+            .get(key)
+            .cloned()
+            .unwrap_or(ConfigSource::Default)
+    }
+
+    /// 配置项是否被显式设置过
+    pub fn is_set(&self, key: &str) -> bool {
+        self.source(key) != ConfigSource::Default
+    }
+
+    /// 配置项是否是为当前的 provider 设置的
+    ///
+    /// 命令行和环境变量总是生效；配置文件和 profile 中的值只有在同一层设置的 provider
+    /// 与当前 provider 相同时才生效，没有设置 provider 的层视为 OpenAI 的配置
+    pub fn is_set_for_provider(&self, key: &str) -> bool {
+        match self.source(key) {
+            ConfigSource::Default => false,
+            ConfigSource::Cli | ConfigSource::Env(_) => true,
+            source => {
+                let provider = self
+                    .layer_providers
+                    .iter()
+                    .rev()
+                    .find(|(layer, _)| *layer == source)
+                    .map_or(Provider::OpenAI, |(_, provider)| *provider);
+                provider == self.provider
+            }
+        }
+    }
+
+    /// 检查调用 API 所需的密钥是否已设置
+    pub fn require_api_key(&self) -> Result<&str> {
+        if self.api_key.is_empty() {
+            let key_env = self
+                .api_key_env
+                .as_deref()
+                .or(self.provider.default_api_key_env());
+            return Err(StcError::ConfigError(match key_env {
+                Some(name) => format!("环境变量 {} 未设置", name),
+                None => "api_key 未设置，请在配置文件中添加 api_key 或设置环境变量 STC_API_KEY"
+                    .to_string(),
+            }));
+        }
+        Ok(&self.api_key)
+    }
+
+    /// 列出每个配置项的值和来源，api_key 只显示末尾 4 位
+    pub fn describe(&self) -> String {
+        let chars: Vec<char> = self.api_key.chars().collect();
+        let masked_key = match chars.len() {
+            0 => String::new(),
+            n if n <= 4 => "*".repeat(n),
+            n => format!("****{}", chars[n - 4..].iter().collect::<String>()),
+        };
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let mut lines = vec![format!(
+            "{:<15} = {}",
+            "profile",
+            optional(self.profile.clone())
+        )];
+        lines.extend(
+            [
+                ("provider", self.provider.to_string()),
+                ("api_key", masked_key),
+                ("api_base", self.api_base.clone()),
+                ("model", self.model.clone()),
+                (
+                    "temperature",
+                    optional(self.temperature.map(|t| t.to_string())),
+                ),
+                ("concurrency", self.concurrency.to_string()),
+                (
+                    "context_size",
+                    optional(self.context_size.map(|n| n.to_string())),
+                ),
+                ("keep_alive", optional(self.keep_alive.clone())),
+                (
+                    "template_dir",
+                    optional(self.template_dir.as_ref().map(|d| d.display().to_string())),
+                ),
+                (
+                    "output_format",
+                    optional(self.output_format.map(|f| f.to_string())),
+                ),
+                ("layout", self.layout.to_string()),
+                ("resume", self.resume.to_string()),
+                ("cache", self.cache.to_string()),
+                (
+                    "cache_dir",
+                    optional(self.cache_dir.as_ref().map(|d| d.display().to_string())),
+                ),
+                ("max_attempts", self.max_attempts.to_string()),
+                ("response_mode", self.response_mode.to_string()),
+                ("chunk_tokens", self.chunk_tokens.to_string()),
+                ("min_cues", self.min_cues.to_string()),
+                ("max_cues", self.max_cues.to_string()),
+                ("scene_gap_ms", self.scene_gap_ms.to_string()),
+                (
+                    "max_line_width",
+                    optional(self.max_line_width.map(|n| n.to_string())),
+                ),
+                ("max_lines", self.max_lines.to_string()),
+                ("context_window", self.context_window.to_string()),
+                ("merge_sentences", self.merge_sentences.to_string()),
+                (
+                    "glossary",
+                    optional(self.glossary.as_ref().map(|p| p.display().to_string())),
+                ),
+                (
+                    "ass_styles",
+                    format!(
+                        "translated {} {}, original {} {}",
+                        self.ass_styles.translated.font,
+                        self.ass_styles.translated.size,
+                        self.ass_styles.original.font,
+                        self.ass_styles.original.size
+                    ),
+                ),
+            ]
+            .into_iter()
+            .map(|(key, value)| format!("{:<15} = {} ({})", key, value, self.source(key))),
+        );
+        lines.push(format!("{:<15} = {}", "profiles", self.profiles.join(", ")));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_temp(name: &str, content: &str) -> PathBuf {
+        let dir = crate::mock::temp_dir("config");
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::load_with(&[], |_| None, &ConfigOverrides::default()).unwrap();
+
+        assert_eq!(config.provider, Provider::OpenAI);
+        assert_eq!(config.api_base, DEFAULT_API_BASE);
+        assert_eq!(config.model, DEFAULT_MODEL);
+        assert_eq!(config.concurrency, DEFAULT_CONCURRENCY);
+        assert_eq!(config.max_attempts, DEFAULT_MAX_ATTEMPTS);
+        assert_eq!(config.context_window, ContextWindow::Full);
+        assert_eq!(config.source("model"), ConfigSource::Default);
+        assert!(config.require_api_key().is_err());
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let user = write_temp(
+            "user.toml",
+            "api_key = \"user-key\"\napi_base = \"https://user\"\nmodel = \"user-model\"\n",
+        );
+        let project = write_temp("project.json", r#"{"model": "project-model"}"#);
+        let overrides = ConfigOverrides {
+            api_base: Some("https://cli".to_string()),
+            ..Default::default()
+        };
+
+        let config = Config::load_with(
+            &[user.clone(), project.clone()],
+            |name| (name == "STC_MODEL").then(|| "env-model".to_string()),
+            &overrides,
+        )
+        .unwrap();
+
+        assert_eq!(config.api_key, "user-key");
+        assert_eq!(config.source("api_key"), ConfigSource::File(user));
+        assert_eq!(config.model, "env-model");
+        assert_eq!(
+            config.source("model"),
+            ConfigSource::Env("STC_MODEL".to_string())
+        );
+        assert_eq!(config.api_base, "https://cli");
+        assert_eq!(config.source("api_base"), ConfigSource::Cli);
+        assert!(config.describe().contains("****-key"));
+    }
+
+    #[test]
+    fn test_env_settings() {
+        let env = |name: &str| match name {
+            "STC_PROVIDER" => Some("ollama".to_string()),
+            "STC_CONCURRENCY" => Some("3".to_string()),
+            "STC_RESPONSE_MODE" => Some("json".to_string()),
+            "STC_MERGE_SENTENCES" => Some("true".to_string()),
+            "STC_CONTEXT_WINDOW" => Some("2 chunks".to_string()),
+            "STC_KEEP_ALIVE" => Some("10".to_string()),
+            _ => None,
+        };
+
+        let config = Config::load_with(&[], env, &ConfigOverrides::default()).unwrap();
+
+        assert_eq!(config.provider, Provider::Ollama);
+        assert_eq!(config.concurrency, 3);
+        assert_eq!(config.response_mode, ResponseMode::Json);
+        assert!(config.merge_sentences);
+        assert_eq!(config.context_window, ContextWindow::Chunks(2));
+        assert_eq!(config.keep_alive.as_deref(), Some("10"));
+        assert_eq!(
+            config.source("concurrency"),
+            ConfigSource::Env("STC_CONCURRENCY".to_string())
+        );
+
+        let env = |name: &str| (name == "STC_MAX_LINES").then(|| "many".to_string());
+        assert!(Config::load_with(&[], env, &ConfigOverrides::default()).is_err());
+    }
+
+    #[test]
+    fn test_explicit_config_file() {
+        let project = write_temp("explicit_project.json", r#"{"api_key": "project-key"}"#);
+        let explicit = write_temp("explicit.toml", "api_key = \"explicit-key\"\n");
+        let overrides = ConfigOverrides {
+            config_path: Some(explicit),
+            ..Default::default()
+        };
+
+        let config = Config::load_with(&[project], |_| None, &overrides).unwrap();
+
+        assert_eq!(config.api_key, "explicit-key");
+    }
+
+    #[test]
+    fn test_missing_explicit_config_file() {
+        let overrides = ConfigOverrides {
+            config_path: Some(PathBuf::from("nonexistent.toml")),
+            ..Default::default()
+        };
+
+        assert!(Config::load_with(&[], |_| None, &overrides).is_err());
+    }
+
+    const PROFILES: &str = r#"
+default_profile = "draft"
+api_key = "top-level-key"
+
+[profiles.draft]
+provider = "openai"
+model = "gpt-4o-mini"
+temperature = 0.3
+
+[profiles.final]
+provider = "anthropic"
+model = "claude-3-5-sonnet-latest"
+api_key_env = "ANTHROPIC_API_KEY"
+concurrency = 2
+"#;
+
+    #[test]
+    fn test_default_profile() {
+        let path = write_temp("profiles_default.toml", PROFILES);
+
+        let config = Config::load_with(&[path], |_| None, &ConfigOverrides::default()).unwrap();
+
+        assert_eq!(config.profile.as_deref(), Some("draft"));
+        assert_eq!(config.profiles, vec!["draft", "final"]);
+        assert_eq!(config.provider, Provider::OpenAI);
+        assert_eq!(config.temperature, Some(0.3));
+        assert_eq!(config.api_key, "top-level-key");
+        assert_eq!(
+            config.source("temperature"),
+            ConfigSource::Profile("draft".to_string())
+        );
+    }
+
+    #[test]
+    fn test_selected_profile() {
+        let path = write_temp("profiles_selected.toml", PROFILES);
+        let overrides = ConfigOverrides {
+            profile: Some("final".to_string()),
+            ..Default::default()
+        };
+
+        let config = Config::load_with(
+            &[path],
+            |name| (name == "ANTHROPIC_API_KEY").then(|| "sk-ant-key".to_string()),
+            &overrides,
+        )
+        .unwrap();
+
+        assert_eq!(config.provider, Provider::Anthropic);
+        assert_eq!(config.model, "claude-3-5-sonnet-latest");
+        assert_eq!(config.api_key, "sk-ant-key");
+        assert_eq!(
+            config.source("api_key"),
+            ConfigSource::Env("ANTHROPIC_API_KEY".to_string())
+        );
+        assert_eq!(config.concurrency, 2);
+        assert_eq!(config.temperature, None);
+    }
+
+    #[test]
+    fn test_unresolved_api_key_env() {
+        let path = write_temp("profiles_unresolved.toml", PROFILES);
+        let overrides = ConfigOverrides {
+            profile: Some("final".to_string()),
+            ..Default::default()
+        };
+
+        let config = Config::load_with(&[path], |_| None, &overrides).unwrap();
+
+        assert_eq!(config.api_key, "");
+        assert_eq!(config.source("api_key"), ConfigSource::Default);
+        assert!(config
+            .require_api_key()
+            .unwrap_err()
+            .to_string()
+            .contains("ANTHROPIC_API_KEY"));
+    }
+
+    #[test]
+    fn test_provider_api_key_env() {
+        let path = write_temp("provider_api_key_env.toml", "api_key = \"sk-openai-key\"\n");
+        let overrides = ConfigOverrides {
+            provider: Some(Provider::Anthropic),
+            ..Default::default()
+        };
+
+        let config = Config::load_with(
+            std::slice::from_ref(&path),
+            |name| (name == "ANTHROPIC_API_KEY").then(|| "sk-ant-key".to_string()),
+            &overrides,
+        )
+        .unwrap();
+        let openai = Config::load_with(
+            &[path],
+            |name| (name == "ANTHROPIC_API_KEY").then(|| "sk-ant-key".to_string()),
+            &ConfigOverrides::default(),
+        )
+        .unwrap();
+
+        assert_eq!(config.api_key, "sk-ant-key");
+        assert_eq!(
+            config.source("api_key"),
+            ConfigSource::Env("ANTHROPIC_API_KEY".to_string())
+        );
+        assert_eq!(openai.api_key, "sk-openai-key");
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let path = write_temp("profiles_unknown.toml", PROFILES);
+        let overrides = ConfigOverrides {
+            profile: Some("missing".to_string()),
+            ..Default::default()
+        };
+
+        let result = Config::load_with(&[path], |_| None, &overrides);
+
+        assert!(result.unwrap_err().to_string().contains("draft, final"));
+    }
+
+    #[test]
+    fn test_ass_styles() {
+        let path = write_temp(
+            "ass_styles.toml",
+            "[ass.original]\nsize = 36\n\n[profiles.cjk.ass.translated]\nfont = \"Noto Sans CJK SC\"\n",
+        );
+        let overrides = ConfigOverrides {
+            profile: Some("cjk".to_string()),
+            ..Default::default()
+        };
+
+        let config = Config::load_with(&[path], |_| None, &overrides).unwrap();
+
+        assert_eq!(config.ass_styles.original.size, 36);
+        assert_eq!(config.ass_styles.translated.font, "Noto Sans CJK SC");
+        assert_eq!(config.ass_styles.translated.size, 64);
+    }
+
+    #[test]
+    fn test_context_window() {
+        let path = write_temp("context_window.toml", "context_window = \"2 chunks\"\n");
+        let invalid = write_temp("invalid_context_window.toml", "context_window = \"2\"\n");
+
+        let config = Config::load_with(&[path], |_| None, &ConfigOverrides::default()).unwrap();
+        let result = Config::load_with(&[invalid], |_| None, &ConfigOverrides::default());
+
+        assert_eq!(config.context_window, ContextWindow::Chunks(2));
+        assert!(config.describe().contains("2 chunks"));
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+
+use crate::backend::{BackendError, BackendResponse, TranslationBackend, Usage};
+
+/// 伪翻译中每一帧字幕的前缀
+pub const ECHO_PREFIX: &str = "[echo] ";
+
+/// 离线后端，不访问网络
+///
+/// 为每一帧字幕加上 `[echo] ` 前缀作为伪翻译，保留 `<T>` 和 `<nl>` 标签，
+/// token 用量由 [`estimate_tokens`](crate::tokens::estimate_tokens) 离线估算
+#[derive(Debug, Default)]
+pub struct EchoBackend {}
+
+impl EchoBackend {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// 生成伪翻译
+    pub fn pseudo_translate(chunk: &str) -> String {
+        chunk
+            .split("<T>")
+            .map(|segment| format!("{}{}", ECHO_PREFIX, segment))
+            .collect::<Vec<_>>()
+            .join("<T>")
+    }
+
+    /// 为 JSON 对象中的每一帧字幕加上前缀，保留原来的编号
+    pub fn pseudo_translate_json(chunk: &str) -> Result<String, BackendError> {
+        let mut object = crate::structured::extract_object(chunk)
+            .ok_or_else(|| BackendError::InvalidRequest("No JSON chunk found".to_string()))?;
+        for value in object.values_mut() {
+            if let Some(text) = value.as_str() {
+                *value = format!("{}{}", ECHO_PREFIX, text).into();
+            }
+        }
+        Ok(serde_json::Value::Object(object).to_string())
+    }
+
+    fn estimate_tokens(text: &str) -> u32 {
+        crate::tokens::estimate_tokens(text) as u32
+    }
+
+    fn respond(prompt: &str, user_message: &str, text: String) -> BackendResponse {
+        let usage = Usage {
+            prompt_tokens: Self::estimate_tokens(prompt) + Self::estimate_tokens(user_message),
+            completion_tokens: Self::estimate_tokens(&text),
+        };
+        BackendResponse { text, usage }
+    }
+}
+
+#[async_trait]
+impl TranslationBackend for EchoBackend {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn model(&self) -> &str {
+        "echo"
+    }
+
+    /// 把整条用户消息当作待翻译的文本
+    async fn chat(
+        &self,
+        prompt: String,
+        user_message: String,
+    ) -> Result<BackendResponse, BackendError> {
+        let text = format!("```\n{}\n```", Self::pseudo_translate(&user_message));
+        Ok(Self::respond(&prompt, &user_message, text))
+    }
+
+    /// 把整条用户消息当作待翻译的 JSON 对象
+    async fn chat_json(
+        &self,
+        prompt: String,
+        user_message: String,
+        _schema: serde_json::Value,
+    ) -> Result<BackendResponse, BackendError> {
+        let text = Self::pseudo_translate_json(&user_message)?;
+        Ok(Self::respond(&prompt, &user_message, text))
+    }
+
+    /// 直接翻译传入的分块，不解析用户消息，自定义模板不影响结果
+    async fn chat_chunk(
+        &self,
+        prompt: String,
+        user_message: String,
+        chunk: &str,
+        schema: Option<serde_json::Value>,
+    ) -> Result<BackendResponse, BackendError> {
+        let text = match schema {
+            Some(_) => Self::pseudo_translate_json(chunk)?,
+            None => format!("```\n{}\n```", Self::pseudo_translate(chunk)),
+        };
+        Ok(Self::respond(&prompt, &user_message, text))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chat_chunk() {
+        let backend = EchoBackend::new();
+        let user_message = "自定义模板，没有标签";
+
+        let response = backend
+            .chat_chunk(
+                "prompt".to_string(),
+                user_message.to_string(),
+                "B<nl>C<T>D",
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, "```\n[echo] B<nl>C<T>[echo] D\n```");
+        assert!(response.usage.prompt_tokens > 0);
+        assert!(response.usage.completion_tokens > 0);
+    }
+
+    #[tokio::test]
+    async fn test_chat_chunk_json() {
+        let backend = EchoBackend::new();
+
+        let response = backend
+            .chat_chunk(
+                "prompt".to_string(),
+                "自定义模板".to_string(),
+                r#"{"1":"B","2":"D"}"#,
+                Some(serde_json::json!({})),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, r#"{"1":"[echo] B","2":"[echo] D"}"#);
+    }
+}
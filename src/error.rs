@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+use crate::backend::BackendError;
+
+/// 库中所有公开函数返回的错误类型
+#[derive(Error, Debug)]
+pub enum StcError {
+    #[error("Config error: {0}")]
+    ConfigError(String),
+    #[error("Parse error: {0}")]
+    ParseError(String),
+    #[error("Template rendering failed: {0}")]
+    TemplateError(#[from] tera::Error),
+    #[error("Translation failed: {0}")]
+    BackendError(#[from] BackendError),
+    #[error("Alignment error: {0}")]
+    AlignmentError(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, StcError>;
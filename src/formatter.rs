@@ -1,4 +1,86 @@
-#[derive(Debug)]
+use std::{fmt, ops::Range, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Result, StcError},
+    tokens::estimate_tokens,
+};
+
+/// 待翻译部分前后各保留多少上下文
+///
+/// 配置和命令行中写作 `full`、`2 chunks` 或 `600 tokens`，默认为整个文件
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ContextWindow {
+    /// 整个字幕文件
+    #[default]
+    Full,
+    /// 前后各 N 个分块，当前分块的其余字幕总是保留
+    Chunks(usize),
+    /// 前后各约 N 个 token，从离待翻译部分最近的字幕开始保留
+    Tokens(usize),
+}
+
+impl FromStr for ContextWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("full") {
+            return Ok(ContextWindow::Full);
+        }
+        let invalid = || format!("无效的上下文窗口 {:?}，应为 full、N chunks 或 N tokens", s);
+        let (count, unit) = s.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let count: usize = count.parse().map_err(|_| invalid())?;
+        match unit.trim().to_ascii_lowercase().as_str() {
+            "chunk" | "chunks" => Ok(ContextWindow::Chunks(count)),
+            "token" | "tokens" => Ok(ContextWindow::Tokens(count)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for ContextWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for ContextWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextWindow::Full => write!(f, "full"),
+            ContextWindow::Chunks(count) => write!(f, "{} chunks", count),
+            ContextWindow::Tokens(count) => write!(f, "{} tokens", count),
+        }
+    }
+}
+
+impl ContextWindow {
+    /// 从近到远保留多少帧上下文，`distance` 为字幕与待翻译部分相隔的分块数
+    fn keep<'a>(&self, nearest_first: impl Iterator<Item = (usize, &'a str)>) -> usize {
+        match *self {
+            ContextWindow::Full => nearest_first.count(),
+            ContextWindow::Chunks(count) => nearest_first
+                .take_while(|(distance, _)| *distance <= count)
+                .count(),
+            ContextWindow::Tokens(budget) => {
+                let mut used = 0;
+                nearest_first
+                    .take_while(|(_, text)| {
+                        used += estimate_tokens(text);
+                        used <= budget
+                    })
+                    .count()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Formatter {
     pub tagged_text: String,
     pub chunk_to_translate: String,
@@ -10,26 +92,111 @@ impl Formatter {
             chunk_to_translate: String::new(),
         }
     }
-    pub fn format(index: usize, source_text_chunks: &Vec<String>) -> Self {
-        let mut formatter = Formatter::new();
-        let before = source_text_chunks[0..index].join("");
-        let current = format!(
-            "<TRANSLATE_THIS>{}</TRANSLATE_THIS>",
-            source_text_chunks[index]
-        );
-        let after = source_text_chunks[index + 1..].join("");
-        let tagged_text = format!("{}{}{}", before, current, after);
-        formatter.tagged_text = tagged_text;
-        formatter.chunk_to_translate = source_text_chunks[index].clone();
-        formatter
+
+    /// 翻译第 `index` 个分块，整个文件的其余部分作为上下文
+    pub fn format(index: usize, source_text_chunks: &[String]) -> Result<Self> {
+        let segments = match source_text_chunks.get(index) {
+            Some(chunk) => 0..chunk.split("<T>").count(),
+            None => 0..0,
+        };
+        Self::format_segments(index, source_text_chunks, segments, ContextWindow::Full)
+    }
+
+    /// 只翻译第 `index` 个分块中 `segments` 范围内的字幕，`window` 以内的其余字幕作为上下文
+    ///
+    /// 超出窗口的字幕替换为说明省略了多少帧的占位文本
+    pub fn format_segments(
+        index: usize,
+        source_text_chunks: &[String],
+        segments: Range<usize>,
+        window: ContextWindow,
+    ) -> Result<Self> {
+        if index >= source_text_chunks.len() {
+            return Err(StcError::AlignmentError(format!(
+                "Chunk {} out of range, only {} chunks",
+                index,
+                source_text_chunks.len()
+            )));
+        }
+        let parts: Vec<&str> = source_text_chunks[index].split("<T>").collect();
+        if segments.is_empty() || segments.end > parts.len() {
+            return Err(StcError::AlignmentError(format!(
+                "Segments {:?} out of range, chunk {} only has {} segments",
+                segments,
+                index,
+                parts.len()
+            )));
+        }
+        // 从近到远排列的上下文，附带与当前分块相隔的分块数
+        let before: Vec<(usize, &str)> = parts[..segments.start]
+            .iter()
+            .rev()
+            .map(|part| (0, *part))
+            .chain(
+                source_text_chunks[..index]
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .flat_map(|(i, chunk)| chunk.rsplit("<T>").map(move |part| (i + 1, part))),
+            )
+            .collect();
+        let after: Vec<(usize, &str)> = parts[segments.end..]
+            .iter()
+            .map(|part| (0, *part))
+            .chain(
+                source_text_chunks[index + 1..]
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, chunk)| chunk.split("<T>").map(move |part| (i + 1, part))),
+            )
+            .collect();
+
+        let kept_before = window.keep(before.iter().copied());
+        let kept_after = window.keep(after.iter().copied());
+        let mut context_before: Vec<String> = before[..kept_before]
+            .iter()
+            .rev()
+            .map(|(_, part)| format!("{}<T>", part))
+            .collect();
+        if kept_before < before.len() {
+            context_before.insert(
+                0,
+                format!("（省略前文 {} 帧字幕）<T>", before.len() - kept_before),
+            );
+        }
+        let mut context_after: Vec<String> = after[..kept_after]
+            .iter()
+            .map(|(_, part)| format!("<T>{}", part))
+            .collect();
+        if kept_after < after.len() {
+            context_after.push(format!(
+                "<T>（省略后文 {} 帧字幕）",
+                after.len() - kept_after
+            ));
+        }
+
+        let chunk_to_translate = parts[segments].join("<T>");
+        Ok(Self {
+            tagged_text: format!(
+                "{}<TRANSLATE_THIS>{}</TRANSLATE_THIS>{}",
+                context_before.concat(),
+                chunk_to_translate,
+                context_after.concat()
+            ),
+            chunk_to_translate,
+        })
     }
 }
 
+#[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn test_format() {
         let mock = crate::mock::Mock::new();
-        let formatter = crate::formatter::Formatter::format(0, &mock.text_splitter.split_result);
+        let formatter =
+            crate::formatter::Formatter::format(0, &mock.text_splitter.split_result).unwrap();
         // eprintln!("{:?}", formatter);
         // eprintln!("tagged_text:{}", formatter.tagged_text);
         // eprintln!("chunk_to_translate:{}", formatter.chunk_to_translate);
@@ -37,8 +204,80 @@ mod test {
             eprintln!("{}:{}", index, item);
         }
         assert_eq!(
-            formatter.chunk_to_translate.split("<T>").count(),
-            crate::GROUP_SIZE
+            formatter.chunk_to_translate,
+            mock.text_splitter.split_result[0]
+        );
+        assert!(formatter.tagged_text.starts_with(&format!(
+            "<TRANSLATE_THIS>{}</TRANSLATE_THIS>",
+            formatter.chunk_to_translate
+        )));
+    }
+
+    #[test]
+    fn test_format_segments() {
+        let chunks = vec!["a<T>b<T>c".to_string(), "d<T>e".to_string()];
+
+        let formatter = Formatter::format_segments(0, &chunks, 1..3, ContextWindow::Full).unwrap();
+
+        assert_eq!(formatter.chunk_to_translate, "b<T>c");
+        assert_eq!(
+            formatter.tagged_text,
+            "a<T><TRANSLATE_THIS>b<T>c</TRANSLATE_THIS><T>d<T>e"
         );
+        assert!(Formatter::format_segments(1, &chunks, 1..3, ContextWindow::Full).is_err());
+    }
+
+    #[test]
+    fn test_context_window() {
+        let chunks = vec![
+            "a<T>b".to_string(),
+            "c<T>d".to_string(),
+            "e<T>f".to_string(),
+            "g<T>h".to_string(),
+        ];
+
+        let formatter =
+            Formatter::format_segments(2, &chunks, 1..2, ContextWindow::Chunks(1)).unwrap();
+        assert_eq!(
+            formatter.tagged_text,
+            "（省略前文 2 帧字幕）<T>c<T>d<T>e<T><TRANSLATE_THIS>f</TRANSLATE_THIS><T>g<T>h"
+        );
+
+        // 每个 ASCII 字母约 1 个 token
+        let formatter =
+            Formatter::format_segments(1, &chunks, 0..2, ContextWindow::Tokens(1)).unwrap();
+        assert_eq!(
+            formatter.tagged_text,
+            "（省略前文 1 帧字幕）<T>b<T><TRANSLATE_THIS>c<T>d</TRANSLATE_THIS><T>e<T>（省略后文 3 帧字幕）"
+        );
+
+        let formatter =
+            Formatter::format_segments(0, &chunks, 0..1, ContextWindow::Chunks(0)).unwrap();
+        assert_eq!(
+            formatter.tagged_text,
+            "<TRANSLATE_THIS>a</TRANSLATE_THIS><T>b<T>（省略后文 6 帧字幕）"
+        );
+    }
+
+    #[test]
+    fn test_parse_context_window() {
+        assert_eq!("full".parse(), Ok(ContextWindow::Full));
+        assert_eq!("2 chunks".parse(), Ok(ContextWindow::Chunks(2)));
+        assert_eq!(" 600  tokens".parse(), Ok(ContextWindow::Tokens(600)));
+        assert!("600".parse::<ContextWindow>().is_err());
+        assert!("two chunks".parse::<ContextWindow>().is_err());
+        assert_eq!(ContextWindow::Tokens(600).to_string(), "600 tokens");
+    }
+
+    #[test]
+    fn test_format_out_of_range() {
+        let chunks = vec!["a".to_string()];
+
+        let result = crate::formatter::Formatter::format(1, &chunks);
+
+        assert!(matches!(
+            result,
+            Err(crate::error::StcError::AlignmentError(_))
+        ));
     }
 }
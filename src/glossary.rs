@@ -0,0 +1,256 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, StcError};
+
+/// 术语表中的一条术语
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    /// 原文中的术语
+    pub source: String,
+    /// 必须使用的译名
+    pub target: String,
+    /// 给模型的补充说明，例如人物的性别或身份
+    #[serde(default)]
+    pub note: Option<String>,
+    /// 是否区分大小写，默认不区分
+    #[serde(default)]
+    pub case_sensitive: bool,
+}
+
+impl Term {
+    /// `text` 中是否出现了原文术语
+    pub fn found_in(&self, text: &str) -> bool {
+        contains(text, &self.source, self.case_sensitive)
+    }
+
+    /// `text` 中是否使用了规定的译名
+    pub fn used_in(&self, text: &str) -> bool {
+        contains(text, &self.target, self.case_sensitive)
+    }
+}
+
+/// JSON 术语表可以是术语数组，也可以是原文到译名的对象
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonGlossary {
+    Terms(Vec<Term>),
+    Map(BTreeMap<String, String>),
+}
+
+/// 人名、地名等术语的固定译名
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    terms: Vec<Term>,
+}
+
+impl Glossary {
+    pub fn new(terms: Vec<Term>) -> Self {
+        Self { terms }
+    }
+
+    /// 按扩展名读取 `.csv`、`.tsv` 或 `.json` 术语表
+    ///
+    /// CSV 和 TSV 的列依次为 source、target、note、case_sensitive，后两列可以省略；
+    /// 第一行以 `source` 开头时作为表头，按表头确定列的顺序
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            StcError::ConfigError(format!("无法读取术语表 {}: {}", path.display(), e))
+        })?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        let glossary = match extension.as_deref() {
+            Some("json") => Self::from_json(&content),
+            Some("csv") => Self::from_delimited(&content, ','),
+            Some("tsv") => Self::from_delimited(&content, '\t'),
+            _ => Err(StcError::ConfigError(
+                "术语表必须是 .csv、.tsv 或 .json 文件".to_string(),
+            )),
+        };
+        glossary.map_err(|e| match e {
+            StcError::ConfigError(message) => {
+                StcError::ConfigError(format!("{}: {}", path.display(), message))
+            }
+            e => e,
+        })
+    }
+
+    pub fn from_json(content: &str) -> Result<Self> {
+        let terms = match serde_json::from_str(content)
+            .map_err(|e| StcError::ConfigError(format!("无效的术语表: {}", e)))?
+        {
+            JsonGlossary::Terms(terms) => terms,
+            JsonGlossary::Map(map) => map
+                .into_iter()
+                .map(|(source, target)| Term {
+                    source,
+                    target,
+                    note: None,
+                    case_sensitive: false,
+                })
+                .collect(),
+        };
+        Self::validated(terms)
+    }
+
+    pub fn from_delimited(content: &str, delimiter: char) -> Result<Self> {
+        let mut columns = ["source", "target", "note", "case_sensitive"].map(String::from);
+        let mut terms = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let fields = split_fields(line, delimiter);
+            if terms.is_empty() && fields[0].trim().eq_ignore_ascii_case("source") {
+                for (column, field) in columns.iter_mut().zip(&fields) {
+                    *column = field.trim().to_ascii_lowercase();
+                }
+                continue;
+            }
+            let field = |name: &str| {
+                columns
+                    .iter()
+                    .position(|column| column == name)
+                    .and_then(|i| fields.get(i))
+                    .map(|field| field.trim().to_string())
+                    .filter(|field| !field.is_empty())
+            };
+            let (Some(source), Some(target)) = (field("source"), field("target")) else {
+                return Err(StcError::ConfigError(format!(
+                    "第 {} 行缺少原文或译名",
+                    number + 1
+                )));
+            };
+            terms.push(Term {
+                source,
+                target,
+                note: field("note"),
+                case_sensitive: field("case_sensitive").is_some_and(|value| {
+                    matches!(value.to_ascii_lowercase().as_str(), "true" | "yes" | "1")
+                }),
+            });
+        }
+        Self::validated(terms)
+    }
+
+    fn validated(terms: Vec<Term>) -> Result<Self> {
+        if let Some(term) = terms
+            .iter()
+            .find(|term| term.source.trim().is_empty() || term.target.trim().is_empty())
+        {
+            return Err(StcError::ConfigError(format!(
+                "术语 {:?} 缺少原文或译名",
+                term.source
+            )));
+        }
+        Ok(Self::new(terms))
+    }
+
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    /// `text` 中出现的术语，用于只把相关的术语放进 prompt
+    pub fn terms_in(&self, text: &str) -> Vec<Term> {
+        self.terms
+            .iter()
+            .filter(|term| term.found_in(text))
+            .cloned()
+            .collect()
+    }
+
+    /// 原文中出现但译文没有使用规定译名的术语
+    pub fn violations(&self, source: &str, translated: &str) -> Vec<&Term> {
+        self.terms
+            .iter()
+            .filter(|term| term.found_in(source) && !term.used_in(translated))
+            .collect()
+    }
+}
+
+/// 拆分一行 CSV 或 TSV，支持用双引号包含分隔符，`""` 表示一个双引号
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("fields is never empty");
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted || field.trim().is_empty() => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+/// `text` 中是否包含 `term`
+///
+/// 以字母或数字开头、结尾的术语要求两侧不是字母或数字，避免 "Art" 匹配到 "Arthur"
+fn contains(text: &str, term: &str, case_sensitive: bool) -> bool {
+    let (text, term) = match case_sensitive {
+        true => (text.to_string(), term.to_string()),
+        false => (text.to_lowercase(), term.to_lowercase()),
+    };
+    let is_word = |c: char| c.is_ascii_alphanumeric();
+    let check_start = term.starts_with(is_word);
+    let check_end = term.ends_with(is_word);
+    text.match_indices(&term).any(|(start, _)| {
+        let end = start + term.len();
+        (!check_start || !text[..start].ends_with(is_word))
+            && (!check_end || !text[end..].starts_with(is_word))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_delimited() {
+        let csv = "source,target,note\n# 人名\nMerlin,梅林,\"wizard, male\"\nLancelot,兰斯洛特\n";
+        let tsv = "Merlin\t梅林\t\ttrue\n";
+
+        let glossary = Glossary::from_delimited(csv, ',').unwrap();
+        let case_sensitive = Glossary::from_delimited(tsv, '\t').unwrap();
+
+        assert_eq!(glossary.terms().len(), 2);
+        assert_eq!(glossary.terms()[0].note.as_deref(), Some("wizard, male"));
+        assert_eq!(glossary.terms()[1].note, None);
+        assert!(case_sensitive.terms()[0].case_sensitive);
+        assert!(Glossary::from_delimited("Merlin\n", ',').is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let list = r#"[{"source": "Merlin", "target": "梅林", "case_sensitive": true}]"#;
+        let map = r#"{"Merlin": "梅林", "Lancelot": "兰斯洛特"}"#;
+
+        assert!(Glossary::from_json(list).unwrap().terms()[0].case_sensitive);
+        assert_eq!(Glossary::from_json(map).unwrap().terms().len(), 2);
+        assert!(Glossary::from_json(r#"[{"source": "Merlin"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_matching() {
+        let glossary = Glossary::from_json(
+            r#"[{"source": "Art", "target": "阿特"}, {"source": "MI6", "target": "军情六处", "case_sensitive": true}]"#,
+        )
+        .unwrap();
+
+        assert!(glossary.terms_in("Arthur, I would give my life").is_empty());
+        assert_eq!(glossary.terms_in("Where is art?")[0].target, "阿特");
+        assert!(glossary.terms_in("mi6 agents").is_empty());
+
+        let violations = glossary.violations("Art works for MI6.", "阿特为军情处工作。");
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].source, "MI6");
+    }
+}
@@ -1,184 +1,1121 @@
 use futures::StreamExt;
 use indicatif::{ProgressState, ProgressStyle};
-use subparse::SubtitleEntry;
+use subparse::{timetypes::TimeSpan, SubtitleEntry, SubtitleFile};
+use tera::Tera;
 
 use crate::{
-    formatter::Formatter, subtitle_combiner::SubtitleCombiner,
-    subtitle_extractor::SubtitleExtractor, text_splitter::TextSplitter, translator::Translator,
-    writer::Writer, GROUP_SIZE,
+    ass::AssStyles,
+    backend::{create_backend, TranslationBackend, Usage},
+    cache::Cache,
+    checkpoint::{self, Checkpoint},
+    config::Config,
+    echo::EchoBackend,
+    error::{Result, StcError},
+    formatter::ContextWindow,
+    glossary::Glossary,
+    repair,
+    retry::RetryPolicy,
+    sentence::Sentences,
+    structured::ResponseMode,
+    subtitle_combiner::{CombinedCue, Layout, SubtitleCombiner},
+    subtitle_extractor::SubtitleExtractor,
+    template::load_templates,
+    text_splitter::{ChunkSettings, TextSplitter},
+    translator::Translator,
+    wrap::WrapSettings,
+    writer::{OutputFormat, Writer},
 };
-use std::{fmt::Write, future::Future, path::PathBuf};
+use std::{
+    fmt::Write,
+    future::Future,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+type TaskOutput = (usize, String, String, Usage);
 
 pub struct Handler {
     subtitle_entries: Vec<SubtitleEntry>,
+    subtitle_file: Option<SubtitleFile>,
+    output_format: OutputFormat,
     subtitle_extractor: SubtitleExtractor,
     text_splitter: TextSplitter,
+    chunk_settings: ChunkSettings,
+    /// 合并跨帧句子时每个翻译单元对应的字幕
+    sentences: Option<Arc<Sentences>>,
     subtitle_combiner: SubtitleCombiner,
+    layout: Layout,
+    ass_styles: AssStyles,
     progress_bar: indicatif::ProgressBar,
+    backend: Arc<dyn TranslationBackend>,
+    templates: Arc<Tera>,
+    concurrency: usize,
+    checkpoint_path: Option<PathBuf>,
+    resume: bool,
+    cache: Option<Arc<Cache>>,
+    retry: Option<Arc<RetryPolicy>>,
+    response_mode: ResponseMode,
+    context_window: ContextWindow,
+    glossary: Option<Arc<Glossary>>,
+    usage: Usage,
 }
 
 impl Handler {
-    pub fn from_path(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
-        let subtitle_entries = crate::parse::parse_file(&path)?;
-        Self::new(subtitle_entries)
+    /// 从字幕文件创建，输出格式默认与输入文件相同
+    pub fn from_path(path: PathBuf, backend: Arc<dyn TranslationBackend>) -> Result<Self> {
+        let subtitle_file = crate::parse::parse_subtitle_file(&path)?;
+        let subtitle_entries = crate::parse::subtitle_entries(&subtitle_file)?;
+        let subtitle_extractor = match subtitle_file {
+            SubtitleFile::SubStationAlpha(_) => {
+                SubtitleExtractor::ass_extractor(&subtitle_entries)?
+            }
+            _ => SubtitleExtractor::extractor(&subtitle_entries)?,
+        };
+        let mut handler = Self::with_extractor(subtitle_entries, subtitle_extractor, backend)?;
+        handler.output_format = OutputFormat::from_subtitle_file(&subtitle_file)?;
+        handler.subtitle_file = Some(subtitle_file);
+        Ok(handler)
     }
 
-    pub fn new(subtitle_entries: Vec<SubtitleEntry>) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(
+        subtitle_entries: Vec<SubtitleEntry>,
+        backend: Arc<dyn TranslationBackend>,
+    ) -> Result<Self> {
         let subtitle_extractor = SubtitleExtractor::extractor(&subtitle_entries)?;
-        let text_splitter = TextSplitter::split_text(&subtitle_extractor.text_info)?;
-        let progress_bar = indicatif::ProgressBar::new(
-            ((subtitle_entries.len() + GROUP_SIZE - 1) / GROUP_SIZE)
-                .try_into()
-                .unwrap(),
-        );
-        progress_bar.set_style(
-            ProgressStyle::with_template(
-                "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}]  {pos}/{len} ({eta})",
-            )
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
-                write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
-            })
-            .progress_chars("#>-"),
-        );
+        Self::with_extractor(subtitle_entries, subtitle_extractor, backend)
+    }
+
+    fn with_extractor(
+        subtitle_entries: Vec<SubtitleEntry>,
+        subtitle_extractor: SubtitleExtractor,
+        backend: Arc<dyn TranslationBackend>,
+    ) -> Result<Self> {
+        let progress_bar = indicatif::ProgressBar::new(0);
+        if let Ok(style) = ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}]  {pos}/{len} ({eta})",
+        ) {
+            progress_bar.set_style(
+                style
+                    .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+                        let _ = write!(w, "{:.1}s", state.eta().as_secs_f64());
+                    })
+                    .progress_chars("#>-"),
+            );
+        }
         progress_bar.set_position(0);
-        Ok(Self {
+        let mut handler = Self {
             subtitle_entries,
+            subtitle_file: None,
+            output_format: OutputFormat::Srt,
             subtitle_extractor,
-            text_splitter,
+            text_splitter: TextSplitter::new(),
+            chunk_settings: ChunkSettings::default(),
+            sentences: None,
             subtitle_combiner: SubtitleCombiner::new(),
+            layout: Layout::default(),
+            ass_styles: AssStyles::default(),
             progress_bar,
-        })
+            backend,
+            templates: Arc::new(load_templates(None)?),
+            concurrency: 10,
+            checkpoint_path: None,
+            resume: true,
+            cache: None,
+            retry: None,
+            response_mode: ResponseMode::default(),
+            context_window: ContextWindow::default(),
+            glossary: None,
+            usage: Usage::default(),
+        };
+        handler.split()?;
+        Ok(handler)
+    }
+
+    /// 使用的 prompt 模板
+    pub fn templates(mut self, templates: Tera) -> Self {
+        self.templates = Arc::new(templates);
+        self
+    }
+
+    /// 输出的字幕格式
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// 双语字幕的排列方式
+    pub fn layout(mut self, value: Layout) -> Self {
+        self.layout = value;
+        self
+    }
+
+    /// 译文的换行规则
+    pub fn wrap(mut self, value: Option<WrapSettings>) -> Self {
+        self.subtitle_combiner = SubtitleCombiner::new().wrap(value);
+        self
+    }
+
+    /// 新建 ASS 文件时译文和原文使用的样式
+    pub fn ass_styles(mut self, value: AssStyles) -> Self {
+        self.ass_styles = value;
+        self
+    }
+
+    /// 同时进行的翻译请求数量
+    pub fn concurrency(mut self, value: usize) -> Self {
+        self.concurrency = value.max(1);
+        self
+    }
+
+    /// 把完成的分块写入检查点文件，重新运行时跳过这些分块
+    pub fn checkpoint(mut self, path: PathBuf) -> Self {
+        self.checkpoint_path = Some(path);
+        self
+    }
+
+    /// 是否从已有的检查点继续，为 `false` 时忽略旧的检查点重新翻译
+    pub fn resume(mut self, value: bool) -> Self {
+        self.resume = value;
+        self
+    }
+
+    /// 持久化的翻译缓存，相同的请求不再调用后端
+    pub fn cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// 后端请求失败时的重试策略
+    pub fn retry(mut self, retry: Arc<RetryPolicy>) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// 按 token 预算重新划分分块
+    pub fn chunking(mut self, settings: ChunkSettings) -> Result<Self> {
+        self.chunk_settings = settings;
+        self.split()?;
+        Ok(self)
+    }
+
+    /// 把跨越多帧字幕的句子合并后整句翻译，再把译文按比例分回原来的字幕
+    pub fn merge_sentences(mut self, enabled: bool) -> Result<Self> {
+        self.sentences = enabled.then(|| {
+            Arc::new(Sentences::group(
+                &self.subtitle_extractor.text_info,
+                &self.subtitle_extractor.timespans,
+            ))
+        });
+        self.split()?;
+        Ok(self)
+    }
+
+    /// 按当前设置把字幕或合并后的句子划分为分块
+    fn split(&mut self) -> Result<()> {
+        let timespans = &self.subtitle_extractor.timespans;
+        // 每帧字幕与上一帧之间的静默
+        let gaps: Vec<i64> = timespans
+            .iter()
+            .enumerate()
+            .map(|(index, span)| match index.checked_sub(1) {
+                Some(previous) => span.start.msecs() - timespans[previous].end.msecs(),
+                None => 0,
+            })
+            .collect();
+        let (units, gaps) = match &self.sentences {
+            Some(sentences) => (
+                sentences.text_info(),
+                sentences.first_cues().map(|cue| gaps[cue]).collect(),
+            ),
+            None => (self.subtitle_extractor.text_info.clone(), gaps),
+        };
+        self.text_splitter = TextSplitter::split_timed(&units, &gaps, &self.chunk_settings)?;
+        Ok(())
+    }
+
+    /// 每个分块中第一个翻译单元的下标
+    fn chunk_offsets(&self) -> Vec<usize> {
+        self.text_splitter
+            .split_result
+            .iter()
+            .scan(0, |offset, chunk| {
+                let first = *offset;
+                *offset += chunk.split("<T>").count();
+                Some(first)
+            })
+            .collect()
+    }
+
+    /// 第 `index` 个分块对应的字幕原文，合并句子时展开为每帧字幕
+    fn chunk_source(&self, index: usize, offset: usize) -> String {
+        let chunk = &self.text_splitter.split_result[index];
+        match &self.sentences {
+            Some(sentences) => sentences.source(offset..offset + chunk.split("<T>").count()),
+            None => chunk.clone(),
+        }
+    }
+
+    /// 要求模型返回译文的方式
+    pub fn response_mode(mut self, value: ResponseMode) -> Self {
+        self.response_mode = value;
+        self
+    }
+
+    /// 每次请求在待翻译部分前后附带的上下文
+    pub fn context_window(mut self, value: ContextWindow) -> Self {
+        self.context_window = value;
+        self
+    }
+
+    /// 术语表，翻译后检查译文是否使用了规定的译名
+    pub fn glossary(mut self, glossary: Arc<Glossary>) -> Self {
+        self.glossary = Some(glossary);
+        self
+    }
+
+    /// 获取本次翻译累计消耗的 token
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    fn writer(&self) -> Writer {
+        Writer::new(self.output_format)
+            .layout(self.layout)
+            .ass_styles(self.ass_styles.clone())
+    }
+
+    /// 所有字幕条目的原文，没有文本的条目为空字符串
+    fn original_cues(&self) -> Vec<(TimeSpan, CombinedCue)> {
+        self.subtitle_entries
+            .iter()
+            .map(|entry| {
+                let cue = CombinedCue {
+                    translated: None,
+                    original: entry.line.clone().unwrap_or_default(),
+                };
+                (entry.timespan, cue)
+            })
+            .collect()
+    }
+
+    /// 按输出格式生成只包含原文的字幕，用于 `Layout::Separate`
+    pub fn render_original(&self) -> Result<String> {
+        self.writer()
+            .render(&self.original_cues(), self.subtitle_file.as_ref())
     }
 
     pub async fn handle_translator(
         &mut self,
         source_language: String,
         target_language: String,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        // if the length of subtitle_entries is not divisible by GROUP_SIZE, then round up
-        let chunk_count = (self.subtitle_entries.len() + GROUP_SIZE - 1) / GROUP_SIZE;
+    ) -> Result<String> {
+        let chunk_count = self.text_splitter.split_result.len();
+        self.progress_bar.set_length(chunk_count as u64);
+        let mut checkpoint = match &self.checkpoint_path {
+            Some(path) => Some(Checkpoint::load(
+                path.clone(),
+                self.checkpoint_key(&source_language, &target_language)?,
+                self.resume,
+            )),
+            None => None,
+        };
 
-        let tasks = self.create_translation_tasks(chunk_count, &source_language, &target_language);
-        let results = self
-            .execute_translation_tasks(tasks, chunk_count)
-            .await
-            .expect("Failed to execute translation tasks");
-        let final_srt_content = self.combine_translation_results(results)?;
+        let offsets = self.chunk_offsets();
+        let mut results = Vec::with_capacity(chunk_count);
+        let mut pending = Vec::with_capacity(chunk_count);
+        for (index, &offset) in offsets.iter().enumerate() {
+            match checkpoint.as_ref().and_then(|c| c.get(index)) {
+                Some(translated_text) => results.push((
+                    index,
+                    translated_text.to_string(),
+                    self.chunk_source(index, offset),
+                )),
+                None => pending.push(index),
+            }
+        }
+        if !results.is_empty() {
+            eprintln!(
+                "Resuming from checkpoint: {}/{} chunks already translated",
+                results.len(),
+                chunk_count
+            );
+            self.progress_bar.set_position(results.len() as u64);
+        }
+
+        let tasks = self.create_translation_tasks(&pending, &source_language, &target_language);
+        results.extend(
+            self.execute_translation_tasks(tasks, checkpoint.as_mut())
+                .await?,
+        );
+        results.sort_by_key(|(index, _, _)| *index);
+        let cues = self.combine_translation_results(results)?;
+        let content = self.writer().render(&cues, self.subtitle_file.as_ref())?;
 
         self.progress_bar.finish_with_message("done");
-        Ok(final_srt_content)
+        let violations = self.glossary_violations(&cues);
+        if !violations.is_empty() {
+            for violation in &violations {
+                eprintln!("{}", violation);
+            }
+            eprintln!(
+                "Glossary: {} lines do not use the required terms",
+                violations.len()
+            );
+        }
+        Ok(content)
+    }
+
+    /// 检查点的 key，由待翻译文本、语言、后端、模型、返回方式、上下文范围和渲染后的模板计算
+    fn checkpoint_key(&self, source_language: &str, target_language: &str) -> Result<String> {
+        let translator = Translator::new(self.backend.clone(), self.templates.clone())
+            .response_mode(self.response_mode);
+        let prompt = translator.format_prompt(source_language, target_language)?;
+        let user_message = translator.format_user_message(
+            source_language,
+            target_language,
+            String::new(),
+            String::new(),
+        )?;
+        let chunks = self.text_splitter.split_result.join("\n");
+        let mut templates = vec![prompt, user_message];
+        // 术语表改变后之前的译文不再有效
+        if let Some(glossary) = &self.glossary {
+            templates.push(serde_json::to_string(glossary.terms()).unwrap_or_default());
+        }
+        let templates: Vec<&str> = templates.iter().map(String::as_str).collect();
+        Ok(checkpoint::hash(&[
+            &checkpoint::hash(&[&chunks]),
+            source_language,
+            target_language,
+            self.backend.name(),
+            self.backend.model(),
+            &self.response_mode.to_string(),
+            &self.context_window.to_string(),
+            &checkpoint::hash(&templates),
+        ]))
+    }
+
+    /// 每个翻译单元在原字幕文件中对应的条目范围
+    fn unit_lines(&self) -> Vec<Range<usize>> {
+        let indices = &self.subtitle_extractor.indices;
+        match &self.sentences {
+            Some(sentences) => sentences
+                .groups()
+                .iter()
+                .map(|cues| indices[cues.start]..indices[cues.end - 1] + 1)
+                .collect(),
+            None => indices.iter().map(|&line| line..line + 1).collect(),
+        }
     }
 
     fn create_translation_tasks(
         &self,
-        chunk_count: usize,
+        indices: &[usize],
         source_language: &str,
         target_language: &str,
-    ) -> Vec<impl Future<Output = Result<(usize, String, String), String>>> {
-        (0..chunk_count)
-            .map(|index| {
-                let formatter = Formatter::format(index, &self.text_splitter.split_result);
+    ) -> Vec<impl Future<Output = Result<TaskOutput>>> {
+        let chunks: Arc<[String]> = self.text_splitter.split_result.clone().into();
+        let lines: Arc<[Range<usize>]> = self.unit_lines().into();
+        let window = self.context_window;
+        let offsets = self.chunk_offsets();
+        indices
+            .iter()
+            .map(|&index| {
+                let chunks = chunks.clone();
+                let lines = lines.clone();
+                let sentences = self.sentences.clone();
+                let offset = offsets[index];
+                let source = self.chunk_source(index, offset);
                 let source_lang = source_language.to_string();
                 let target_lang = target_language.to_string();
-                let mut translator = Translator::new();
+                let mut translator = Translator::new(self.backend.clone(), self.templates.clone())
+                    .response_mode(self.response_mode);
+                if let Some(cache) = &self.cache {
+                    translator = translator.cache(cache.clone());
+                }
+                if let Some(retry) = &self.retry {
+                    translator = translator.retry(retry.clone());
+                }
+                if let Some(glossary) = &self.glossary {
+                    translator = translator.glossary(glossary.clone());
+                }
 
                 async move {
-                    let result = translator
-                        .translate(
-                            &source_lang,
-                            &target_lang,
-                            formatter.tagged_text,
-                            formatter.chunk_to_translate.clone(),
-                        )
-                        .await;
-
-                    match result {
-                        Ok(_) => Ok((
-                            index,
-                            translator.format_translated_result(),
-                            formatter.chunk_to_translate,
-                        )),
-                        Err(e) => Err(e.to_string()),
-                    }
+                    let (translated_text, usage) = repair::translate_chunk(
+                        &mut translator,
+                        &chunks,
+                        &lines,
+                        index,
+                        window,
+                        &source_lang,
+                        &target_lang,
+                    )
+                    .await?;
+
+                    // 检查点和合并都按原来的字幕保存译文
+                    let translated_text = match &sentences {
+                        Some(sentences) => sentences.expand(offset, &translated_text)?,
+                        None => translated_text,
+                    };
+                    Ok((index, translated_text, source, usage))
                 }
             })
             .collect()
     }
 
     /// use multiple tasks to translate the text
+    ///
+    /// 每完成一个分块就写入检查点，出错时已完成的分块不会丢失
     async fn execute_translation_tasks(
         &mut self,
-        tasks: Vec<impl Future<Output = Result<(usize, String, String), String>>>,
-        chunk_count: usize,
-    ) -> Result<Vec<(usize, String, String)>, Box<dyn std::error::Error>> {
-        let mut results = Vec::with_capacity(chunk_count);
-        let stream = futures::stream::iter(tasks).buffer_unordered(10);
+        tasks: Vec<impl Future<Output = Result<TaskOutput>>>,
+        mut checkpoint: Option<&mut Checkpoint>,
+    ) -> Result<Vec<(usize, String, String)>> {
+        let mut results = Vec::with_capacity(tasks.len());
+        let stream = futures::stream::iter(tasks).buffer_unordered(self.concurrency);
         tokio::pin!(stream);
 
         while let Some(result) = stream.next().await {
-            let (index, translated_text, chunk_to_translate) = result?;
+            let (index, translated_text, chunk_to_translate, usage) = result?;
+            self.usage += usage;
+            if let Some(checkpoint) = checkpoint.as_mut() {
+                checkpoint.save_chunk(index, &translated_text)?;
+            }
             results.push((index, translated_text, chunk_to_translate));
             self.progress_bar.inc(1);
         }
 
-        results.sort_by_key(|(index, _, _)| *index);
         Ok(results)
     }
 
+    /// 检查译文是否使用了术语表规定的译名，返回每一处违反的说明
+    ///
+    /// 合并句子时按整句检查，译名可能被分到同一句的相邻字幕中
+    fn glossary_violations(&self, cues: &[(TimeSpan, CombinedCue)]) -> Vec<String> {
+        let Some(glossary) = &self.glossary else {
+            return Vec::new();
+        };
+        let indices = &self.subtitle_extractor.indices;
+        let groups: Vec<Range<usize>> = match &self.sentences {
+            Some(sentences) => sentences.groups().to_vec(),
+            None => (0..indices.len()).map(|index| index..index + 1).collect(),
+        };
+        groups
+            .into_iter()
+            .filter_map(|group| {
+                let lines = &indices[group];
+                let join = |texts: Vec<&str>| {
+                    texts
+                        .into_iter()
+                        .map(str::to_string)
+                        .reduce(|sentence, text| crate::sentence::join(&sentence, &text))
+                        .unwrap_or_default()
+                };
+                let original = join(lines.iter().map(|&i| cues[i].1.original.as_str()).collect());
+                let translated = join(
+                    lines
+                        .iter()
+                        .map(|&i| cues[i].1.translated.as_deref())
+                        .collect::<Option<_>>()?,
+                );
+                let terms: Vec<String> = glossary
+                    .violations(&original, &translated)
+                    .iter()
+                    .map(|term| format!("\"{}\" -> \"{}\"", term.source, term.target))
+                    .collect();
+                let location = match (lines.first()?, lines.last()?) {
+                    (first, last) if first == last => format!("Line {}", first + 1),
+                    (first, last) => format!("Lines {}-{}", first + 1, last + 1),
+                };
+                (!terms.is_empty()).then(|| format!("{}: expected {}", location, terms.join(", ")))
+            })
+            .collect()
+    }
+
+    /// 合并翻译结果，返回带原时间轴的字幕条目
     fn combine_translation_results(
         &mut self,
         results: Vec<(usize, String, String)>,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let mut final_srt_content = String::with_capacity(self.subtitle_entries.len());
+    ) -> Result<Vec<(TimeSpan, CombinedCue)>> {
+        let mut cues = Vec::with_capacity(self.subtitle_entries.len());
 
         for (_, translated_text, chunk_to_translate) in results {
             let input = crate::subtitle_combiner::CombineInput {
                 combined_text: chunk_to_translate,
                 translated_text,
-                time_info: self.subtitle_extractor.time_info.clone(),
-                number_info: self.subtitle_extractor.number_info.clone(),
+                timespans: self.subtitle_extractor.timespans.clone(),
+                markup: self.subtitle_extractor.markup.clone(),
+                indices: self.subtitle_extractor.indices.clone(),
             };
 
             self.subtitle_combiner.combine(input)?;
-            final_srt_content.push_str(&self.subtitle_combiner.get_content());
+            cues.extend_from_slice(self.subtitle_combiner.get_cues());
         }
 
-        Ok(final_srt_content)
+        let indices = &self.subtitle_extractor.indices;
+        if cues.len() != indices.len() {
+            return Err(StcError::AlignmentError(format!(
+                "Expected {} cues, got {}",
+                indices.len(),
+                cues.len()
+            )));
+        }
+        // 没有文本的条目不参与翻译，按原样保留时间轴
+        let mut all_cues = self.original_cues();
+        for (index, cue) in indices.iter().zip(cues) {
+            all_cues[*index].1 = cue;
+        }
+        Ok(all_cues)
     }
 }
-pub async fn handle_openai_translate(
+/// 使用配置中的 provider 翻译字幕文件，并写入到输入文件旁边
+pub async fn handle_translate(
+    path: PathBuf,
+    source_language: String,
+    target_language: String,
+    config: &Config,
+) -> Result<()> {
+    let backend = create_backend(config)?;
+    let output_path = generate_output_path(&path, &target_language, output_format(&path, config)?);
+    translate_file(
+        path,
+        output_path,
+        source_language,
+        target_language,
+        backend,
+        config,
+    )
+    .await
+}
+
+/// 使用离线的 echo 后端走完整个流程，输出写入 `<stem>.<lang>.dry-run.<ext>`，不消耗 token
+pub async fn handle_dry_run(
+    path: PathBuf,
+    source_language: String,
+    target_language: String,
+    config: &Config,
+) -> Result<()> {
+    let output_path = generate_output_path(
+        &path,
+        &format!("{}.dry-run", target_language),
+        output_format(&path, config)?,
+    );
+    // 离线的伪翻译不需要缓存
+    let mut config = config.clone();
+    config.cache = false;
+    let backend = Arc::new(EchoBackend::new());
+    translate_file(
+        path,
+        output_path,
+        source_language,
+        target_language,
+        backend,
+        &config,
+    )
+    .await
+}
+
+/// 使用指定的翻译后端翻译 `path`，结果写入 `output_path`
+pub async fn translate_file(
     path: PathBuf,
+    output_path: PathBuf,
     source_language: String,
     target_language: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut handler = Handler::from_path(path.clone())?;
+    backend: Arc<dyn TranslationBackend>,
+    config: &Config,
+) -> Result<()> {
+    let templates = load_templates(config.template_dir.as_deref())?;
+    let format = output_format(&path, config)?;
+    let original_path = generate_output_path(&path, &source_language, format);
+    let checkpoint_path = Checkpoint::path_for(&output_path);
+    let retry = Arc::new(RetryPolicy::new(config.max_attempts));
+    let mut handler = Handler::from_path(path, backend)?
+        .chunking(config.chunk_settings())?
+        .merge_sentences(config.merge_sentences)?
+        .wrap(config.wrap_settings())
+        .templates(templates)
+        .output_format(format)
+        .layout(config.layout)
+        .ass_styles(config.ass_styles.clone())
+        .checkpoint(checkpoint_path.clone())
+        .resume(config.resume)
+        .concurrency(config.concurrency)
+        .response_mode(config.response_mode)
+        .context_window(config.context_window)
+        .retry(retry.clone());
+    let cache = config
+        .cache_dir
+        .clone()
+        .filter(|_| config.cache)
+        .map(|dir| Arc::new(Cache::new(dir)));
+    if let Some(cache) = &cache {
+        handler = handler.cache(cache.clone());
+    }
+    if let Some(path) = &config.glossary {
+        handler = handler.glossary(Arc::new(Glossary::load(path)?));
+    }
 
-    let final_srt_content = handler
-        .handle_translator(source_language, target_language.clone())
+    let content = handler
+        .handle_translator(source_language, target_language)
         .await?;
 
-    let output_path = generate_output_path(&path, &target_language);
-    Writer::write_file(final_srt_content, output_path)?;
+    Writer::write_file(content, output_path)?;
+    if config.layout == Layout::Separate {
+        Writer::write_file(handler.render_original()?, original_path)?;
+    }
+    Checkpoint::remove(&checkpoint_path)?;
+    let usage = handler.usage();
+    eprintln!(
+        "Token usage: {} prompt + {} completion = {}",
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.total_tokens()
+    );
+    if let Some(cache) = cache {
+        eprintln!(
+            "Cache: {} hits, {} misses ({})",
+            cache.hits(),
+            cache.misses(),
+            cache.dir().display()
+        );
+    }
+    eprintln!(
+        "Retries: {} ({} rate limited)",
+        retry.retries(),
+        retry.rate_limited()
+    );
     Ok(())
 }
-fn generate_output_path(input_path: &PathBuf, target_language: &str) -> PathBuf {
+
+/// 配置中指定的输出格式，未指定时与输入文件相同
+fn output_format(path: &Path, config: &Config) -> Result<OutputFormat> {
+    config
+        .output_format
+        .map_or_else(|| OutputFormat::from_path(path), Ok)
+}
+
+fn generate_output_path(input_path: &Path, target_language: &str, format: OutputFormat) -> PathBuf {
     let file_name = input_path
         .file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or("output");
-    input_path.with_file_name(format!("{}.{}.srt", file_name, target_language))
+    // 输出格式与输入相同时沿用原来的扩展名，如 `.ssa`
+    let extension = match input_path.extension().and_then(|s| s.to_str()) {
+        Some(extension) if OutputFormat::from_path(input_path).ok() == Some(format) => extension,
+        _ => format.extension(),
+    };
+    input_path.with_file_name(format!("{}.{}.{}", file_name, target_language, extension))
 }
 
 #[cfg(test)]
 mod test {
+    use super::Handler;
+    use crate::{
+        config::Config,
+        error::StcError,
+        glossary::Glossary,
+        mock::{temp_dir, MockBackend},
+        retry::RetryPolicy,
+        structured::ResponseMode,
+        text_splitter::ChunkSettings,
+    };
+    use std::sync::Arc;
+
+    /// 固定每 10 条字幕一个分块，test.srt 分为 6 块
+    const TEN_CUES: ChunkSettings = ChunkSettings {
+        max_tokens: usize::MAX,
+        min_cues: 10,
+        max_cues: 10,
+        scene_gap_ms: u64::MAX,
+    };
+
     #[tokio::test]
     async fn test_handle() {
-        let path = std::path::PathBuf::from("example/WOLFS.en.srt");
+        let dir = temp_dir("handle");
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+        let backend = Arc::new(MockBackend::new());
+
+        crate::handler::translate_file(
+            path,
+            dir.join("test.zh_CN.srt"),
+            "en".to_string(),
+            "zh_CN".to_string(),
+            backend.clone(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.srt")).unwrap();
+        assert!(output.contains("He swore an oath."));
+        // 默认的分块设置下，test.srt 的 60 帧字幕分成三块
+        let handler = Handler::from_path("test.srt".into(), backend.clone()).unwrap();
+        let ranges: Vec<std::ops::Range<usize>> = handler
+            .chunk_offsets()
+            .into_iter()
+            .zip(&handler.text_splitter.split_result)
+            .map(|(offset, chunk)| offset..offset + chunk.split("<T>").count())
+            .collect();
+        assert_eq!(ranges, [0..20, 20..40, 40..60]);
+        assert_eq!(backend.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_handle_backend_error() {
+        let backend = Arc::new(MockBackend::with_results(vec![Err(
+            crate::backend::BackendError::RequestError("connection reset".to_string()),
+        )]));
+        let mut handler = Handler::from_path("test.srt".into(), backend)
+            .unwrap()
+            .concurrency(1);
 
-        crate::handler::handle_openai_translate(path, "en".to_string(), "zh_CN".to_string())
+        let result = handler
+            .handle_translator("en".to_string(), "zh_CN".to_string())
+            .await;
+
+        assert!(matches!(result, Err(StcError::BackendError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_retry() {
+        let backend = Arc::new(MockBackend::with_results(vec![Err(
+            crate::backend::BackendError::RequestError("connection reset".to_string()),
+        )]));
+        let retry = Arc::new(RetryPolicy::new(2).base_delay(std::time::Duration::ZERO));
+        let mut handler = Handler::from_path("test.srt".into(), backend.clone())
+            .unwrap()
+            .chunking(TEN_CUES)
+            .unwrap()
+            .retry(retry.clone())
+            .concurrency(1);
+
+        handler
+            .handle_translator("en".to_string(), "zh_CN".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(retry.retries(), 1);
+        assert_eq!(backend.requests().len(), 7);
+    }
+
+    fn glossary() -> Arc<Glossary> {
+        let glossary = Glossary::from_delimited("Merlin,梅林,wizard\nExcalibur,王者之剑\n", ',');
+        Arc::new(glossary.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_glossary_in_prompt() {
+        let backend = Arc::new(MockBackend::new());
+        let mut handler = Handler::from_path("test.srt".into(), backend.clone())
+            .unwrap()
+            .chunking(TEN_CUES)
+            .unwrap()
+            .glossary(glossary())
+            .concurrency(1);
+
+        handler
+            .handle_translator("en".to_string(), "zh_CN".to_string())
+            .await
+            .unwrap();
+
+        let requests = backend.requests();
+        assert!(requests[0].contains("- Merlin → 梅林（wizard）"));
+        assert!(!requests[0].contains("Excalibur"));
+        // 只有出现术语的分块才带术语表
+        assert!(requests.iter().any(|request| !request.contains("术语表")));
+    }
+
+    #[test]
+    fn test_glossary_violations() {
+        let backend = Arc::new(MockBackend::new());
+        let handler = Handler::from_path("test.srt".into(), backend)
+            .unwrap()
+            .glossary(glossary());
+        let mut cues = handler.original_cues();
+        cues[6].1.translated = Some("你的所谓魔法师，\n梅林，帮不了我们。".to_string());
+        cues[12].1.translated = Some("但这个Merlin根本不是巫师。".to_string());
+
+        let violations = handler.glossary_violations(&cues);
+
+        assert_eq!(violations, ["Line 13: expected \"Merlin\" -> \"梅林\""]);
+    }
+
+    #[test]
+    fn test_glossary_violations_merge_sentences() {
+        let backend = Arc::new(MockBackend::new());
+        let handler = Handler::from_path("test.srt".into(), backend.clone())
+            .unwrap()
+            .glossary(glossary());
+        let merged = Handler::from_path("test.srt".into(), backend)
+            .unwrap()
+            .merge_sentences(true)
+            .unwrap()
+            .glossary(glossary());
+        // 第 12、13 帧是同一句："I would give my life for you," / "but this Merlin is no wizard."
+        let cues = |first: &str, second: &str| {
+            let mut cues = handler.original_cues();
+            cues[11].1.translated = Some(first.to_string());
+            cues[12].1.translated = Some(second.to_string());
+            cues
+        };
+        let moved = cues("我愿为你献出生命，但梅林", "根本不是巫师。");
+        let split = cues("我愿为你献出生命，但这个梅", "林根本不是巫师。");
+        let missing = cues("我愿为你献出生命，", "但这个魔法师根本不是巫师。");
+
+        assert_eq!(
+            handler.glossary_violations(&moved),
+            ["Line 13: expected \"Merlin\" -> \"梅林\""]
+        );
+        assert!(merged.glossary_violations(&moved).is_empty());
+        assert!(merged.glossary_violations(&split).is_empty());
+        assert_eq!(
+            merged.glossary_violations(&missing),
+            ["Lines 12-13: expected \"Merlin\" -> \"梅林\""]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let dir = temp_dir("dry_run");
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+
+        crate::handler::handle_dry_run(
+            path,
+            "en".to_string(),
+            "zh_CN".to_string(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.srt")).unwrap();
+        let entries = output.split("\n\n").filter(|s| !s.trim().is_empty());
+        assert_eq!(entries.count(), 60);
+        assert!(output.contains("[echo] He swore an oath.\nHe swore an oath."));
+        assert!(output.contains("[echo] <i>Magic does exist.</i>\n<i>Magic does exist.</i>"));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_json_mode() {
+        let dir = temp_dir("dry_run_json");
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+        let mut config = Config::default();
+        config.response_mode = ResponseMode::Json;
+
+        crate::handler::handle_dry_run(path, "en".to_string(), "zh_CN".to_string(), &config)
+            .await
+            .unwrap();
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.srt")).unwrap();
+        assert_eq!(output.matches("[echo] ").count(), 60);
+        assert!(output.contains("[echo] He swore an oath.\nHe swore an oath."));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_custom_user_message() {
+        let dir = temp_dir("dry_run_custom_template");
+        let template_dir = dir.join("templates");
+        std::fs::create_dir_all(&template_dir).unwrap();
+        std::fs::write(
+            template_dir.join("user_message.txt"),
+            "把下面的字幕翻译成{{ target_language }}：\n{{ chunk_to_translate }}\n",
+        )
+        .unwrap();
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+        let mut config = Config::default();
+        config.template_dir = Some(template_dir);
+
+        crate::handler::handle_dry_run(path, "en".to_string(), "zh_CN".to_string(), &config)
             .await
             .unwrap();
-        eprintln!("done");
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.srt")).unwrap();
+        assert_eq!(output.matches("[echo] ").count(), 60);
+        assert!(output.contains("[echo] He swore an oath.\nHe swore an oath."));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_merge_sentences() {
+        let dir = temp_dir("dry_run_sentences");
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+        let mut config = Config::default();
+        config.merge_sentences = true;
+
+        crate::handler::handle_dry_run(path, "en".to_string(), "zh_CN".to_string(), &config)
+            .await
+            .unwrap();
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.srt")).unwrap();
+        let entries = output.split("\n\n").filter(|s| !s.trim().is_empty());
+        assert_eq!(entries.count(), 60);
+        // 跨帧的句子只翻译一次，译文分到原来的每一帧
+        assert!(output.matches("[echo] ").count() < 60);
+        assert!(output.contains("[echo] He swore an oath.\nHe swore an oath."));
+    }
+
+    #[test]
+    fn test_output_path_keeps_extension() {
+        use crate::writer::OutputFormat;
+        use std::path::{Path, PathBuf};
+
+        let path = Path::new("movie/test.ssa");
+
+        assert_eq!(
+            super::generate_output_path(path, "zh_CN", OutputFormat::Ass),
+            PathBuf::from("movie/test.zh_CN.ssa")
+        );
+        assert_eq!(
+            super::generate_output_path(path, "zh_CN", OutputFormat::Srt),
+            PathBuf::from("movie/test.zh_CN.srt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_keeps_ass_format() {
+        let dir = temp_dir("dry_run_ass");
+        let path = dir.join("test.ass");
+        std::fs::copy("test.ass", &path).unwrap();
+
+        crate::handler::handle_dry_run(
+            path,
+            "en".to_string(),
+            "zh_CN".to_string(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.ass")).unwrap();
+        assert!(output.contains("Title: stc test"));
+        assert!(output.contains("Style: Sign,Arial,48"));
+        assert!(output.contains(
+            "Dialogue: 0,0:01:36.26,0:01:38.30,Default,,0,0,0,,{\\rTranslated}[echo] He swore an oath.\\N{\\rOriginal}He swore an oath."
+        ));
+        assert!(output.contains(",Sign,,0,0,0,,"));
+    }
+
+    #[tokio::test]
+    async fn test_output_format_override() {
+        let dir = temp_dir("output_format");
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+        let mut config = Config::default();
+        config.output_format = Some(crate::writer::OutputFormat::Vtt);
+
+        crate::handler::handle_dry_run(path, "en".to_string(), "zh_CN".to_string(), &config)
+            .await
+            .unwrap();
+
+        let output = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.vtt")).unwrap();
+        assert!(output.starts_with("WEBVTT\n\n00:01:34.095 --> 00:01:36.180\n"));
+    }
+
+    #[tokio::test]
+    async fn test_separate_layout() {
+        let dir = temp_dir("separate_layout");
+        let path = dir.join("test.srt");
+        std::fs::copy("test.srt", &path).unwrap();
+        let mut config = Config::default();
+        config.layout = crate::subtitle_combiner::Layout::Separate;
+
+        crate::handler::handle_dry_run(path, "en".to_string(), "zh_CN".to_string(), &config)
+            .await
+            .unwrap();
+
+        let translated = std::fs::read_to_string(dir.join("test.zh_CN.dry-run.srt")).unwrap();
+        let original = std::fs::read_to_string(dir.join("test.en.srt")).unwrap();
+        assert!(translated.contains("[echo] He swore an oath.\n\n"));
+        assert!(!translated.contains("\nHe swore an oath."));
+        assert!(original.contains("\nHe swore an oath.\n\n"));
+        assert!(!original.contains("[echo]"));
+    }
+
+    #[tokio::test]
+    async fn test_entries_without_text() {
+        let mut subtitle_entries =
+            crate::parse::parse_file(std::path::Path::new("test.srt")).unwrap();
+        subtitle_entries[1].line = None;
+        subtitle_entries[2].line = Some(String::new());
+        let backend = Arc::new(MockBackend::new());
+        let mut handler = Handler::new(subtitle_entries, backend).unwrap();
+
+        let output = handler
+            .handle_translator("en".to_string(), "zh_CN".to_string())
+            .await
+            .unwrap();
+
+        let reparsed = subparse::parse_str(subparse::SubtitleFormat::SubRip, &output, 25.0)
+            .unwrap()
+            .get_subtitle_entries()
+            .unwrap();
+        assert_eq!(reparsed.len(), 60);
+        assert_eq!(reparsed[1].line.as_deref(), Some(""));
+        assert!(output.contains("\n00:01:36,580 --> 00:01:38,725\n\n"));
+        assert!(output.starts_with(
+            "1\n00:01:34,095 --> 00:01:36,180\nWhere in hell is\nyour so-called magician?\nWhere in hell is\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint() {
+        let dir = temp_dir("resume");
+        let checkpoint_path = dir.join("test.zh_CN.srt.checkpoint.json");
+        let failing = Arc::new(MockBackend::with_results(vec![
+            Ok(format!("```\n{}\n```", ["第一块"; 10].join("<T>"))),
+            Ok(format!("```\n{}\n```", ["第二块"; 10].join("<T>"))),
+            Err(crate::backend::BackendError::RequestError(
+                "connection reset".to_string(),
+            )),
+        ]));
+        let mut handler = Handler::from_path("test.srt".into(), failing)
+            .unwrap()
+            .chunking(TEN_CUES)
+            .unwrap()
+            .checkpoint(checkpoint_path.clone())
+            .concurrency(1);
+
+        let result = handler
+            .handle_translator("en".to_string(), "zh_CN".to_string())
+            .await;
+
+        assert!(result.is_err());
+        assert!(checkpoint_path.exists());
+
+        let backend = Arc::new(MockBackend::new());
+        let mut handler = Handler::from_path("test.srt".into(), backend.clone())
+            .unwrap()
+            .chunking(TEN_CUES)
+            .unwrap()
+            .checkpoint(checkpoint_path.clone())
+            .concurrency(1);
+
+        handler
+            .handle_translator("en".to_string(), "zh_CN".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(backend.requests().len(), 4);
+    }
+
+    #[test]
+    fn test_checkpoint_key_settings() {
+        let handler =
+            || Handler::from_path("test.srt".into(), Arc::new(MockBackend::new())).unwrap();
+        let key = |handler: Handler| handler.checkpoint_key("en", "zh_CN").unwrap();
+
+        let default = key(handler());
+
+        assert_eq!(key(handler()), default);
+        assert_ne!(key(handler().response_mode(ResponseMode::Json)), default);
+        assert_ne!(
+            key(handler().context_window(crate::formatter::ContextWindow::Chunks(1))),
+            default
+        );
     }
 }
@@ -1,49 +1,45 @@
-use config::Config;
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
-
-#[macro_use]
-extern crate lazy_static;
+pub mod anthropic;
+pub mod ass;
+pub mod backend;
+pub mod cache;
+pub mod checkpoint;
 pub mod config;
+pub mod echo;
+pub mod error;
 pub mod formatter;
+pub mod glossary;
 pub mod handler;
+pub mod local;
+pub mod markup;
+#[cfg(test)]
 pub mod mock;
 pub mod openai;
 pub mod parse;
+pub mod repair;
+pub mod retry;
+pub mod sentence;
+pub mod srt;
+pub mod structured;
 pub mod subtitle_combiner;
 pub mod subtitle_extractor;
+pub mod template;
 pub mod text_splitter;
+pub mod tokens;
 pub mod translator;
+pub mod wrap;
 pub mod writer;
-/// global constants
-const GROUP_SIZE: usize = 10;
-static CONFIG: Lazy<Mutex<Config>> = Lazy::new(|| {
-    let config = Config::read_config_from_file("./config.json").expect("Failed to read config");
-    Mutex::new(config)
-});
-lazy_static! {
-    pub static ref TEMPLATES: tera::Tera = {
-        let tera = match tera::Tera::new("./templates/*") {
-            Ok(t) => t,
-            Err(e) => {
-                println!("Parsing error(s): {}", e);
-                ::std::process::exit(1);
-            }
-        };
-        tera
-    };
-}
 
 #[cfg(test)]
 mod test {
-    use crate::CONFIG;
+    use crate::config::{Config, ConfigOverrides, ConfigSource};
 
     #[test]
     fn test_tera() {
         let mut context = tera::Context::new();
         context.insert("source_lang", "en");
         context.insert("target_lang", "ja");
-        match crate::TEMPLATES.render("prompt.txt", &context) {
+        let templates = crate::template::load_templates(None).unwrap();
+        match templates.render("prompt.txt", &context) {
             Ok(s) => println!("{:?}", s),
             Err(e) => {
                 println!("Error: {}", e);
@@ -77,12 +73,16 @@ mod test {
             pb.inc(1);
         }
         pb.finish_with_message("done");
-        assert!(true);
     }
     #[test]
     fn test_config() {
-        let config = CONFIG.lock().unwrap();
-        println!("{:?}", config);
-        assert!(!config.api_key.is_empty());
+        let overrides = ConfigOverrides {
+            model: Some("gpt-4o".to_string()),
+            ..Default::default()
+        };
+        let config = Config::load_with(&[], |_| None, &overrides).unwrap();
+        println!("{}", config.describe());
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.source("model"), ConfigSource::Cli);
     }
 }
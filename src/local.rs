@@ -0,0 +1,393 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{BackendError, BackendResponse, TranslationBackend, Usage};
+
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+pub const DEFAULT_LLAMA_CPP_URL: &str = "http://localhost:8080";
+
+/// 本地推理服务的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LocalServer {
+    /// Ollama 的 `/api/chat` 接口
+    Ollama,
+    /// llama.cpp server 的 `/v1/chat/completions` 接口
+    LlamaCpp,
+}
+
+impl LocalServer {
+    pub fn default_url(&self) -> &'static str {
+        match self {
+            LocalServer::Ollama => DEFAULT_OLLAMA_URL,
+            LocalServer::LlamaCpp => DEFAULT_LLAMA_CPP_URL,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ChatMessage {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OllamaRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    /// 约束回复格式的 JSON schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaResponse {
+    message: OllamaMessage,
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: u32,
+    #[serde(default)]
+    eval_count: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaMessage {
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct LlamaCppRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LlamaCppResponse {
+    choices: Vec<LlamaCppChoice>,
+    usage: Option<LlamaCppUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LlamaCppChoice {
+    message: OllamaMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LlamaCppUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+/// 本地大模型后端，不需要 API key
+#[derive(Debug)]
+pub struct LocalLlm {
+    server: LocalServer,
+    api_base: String,
+    model: String,
+    temperature: Option<f32>,
+    context_size: Option<u32>,
+    keep_alive: Option<String>,
+    client: reqwest::Client,
+}
+
+impl LocalLlm {
+    pub fn new(server: LocalServer, model: &str) -> Self {
+        Self {
+            server,
+            api_base: server.default_url().to_string(),
+            model: model.to_string(),
+            temperature: None,
+            context_size: None,
+            keep_alive: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// 上下文长度，仅 Ollama 支持按请求设置
+    pub fn with_context_size(mut self, context_size: Option<u32>) -> Self {
+        self.context_size = context_size;
+        self
+    }
+
+    /// 模型在内存中保留的时间，如 `5m`，仅 Ollama 支持
+    pub fn with_keep_alive(mut self, keep_alive: Option<String>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    fn messages(prompt: String, user_message: String) -> Vec<ChatMessage> {
+        vec![
+            ChatMessage {
+                role: "system",
+                content: prompt,
+            },
+            ChatMessage {
+                role: "user",
+                content: user_message,
+            },
+        ]
+    }
+
+    async fn post<T: Serialize>(&self, path: &str, body: &T) -> Result<String, BackendError> {
+        let response = self
+            .client
+            .post(format!("{}{}", self.api_base, path))
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| BackendError::RequestError(e.to_string()))?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| BackendError::RequestError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(BackendError::http(status, &headers, body));
+        }
+        Ok(body)
+    }
+
+    async fn chat_ollama(
+        &self,
+        prompt: String,
+        user_message: String,
+        schema: Option<serde_json::Value>,
+    ) -> Result<BackendResponse, BackendError> {
+        let request = OllamaRequest {
+            model: &self.model,
+            messages: Self::messages(prompt, user_message),
+            stream: false,
+            keep_alive: self.keep_alive.as_deref(),
+            options: (self.context_size.is_some() || self.temperature.is_some()).then_some(
+                OllamaOptions {
+                    num_ctx: self.context_size,
+                    temperature: self.temperature,
+                },
+            ),
+            format: schema,
+        };
+        let body = self.post("/api/chat", &request).await?;
+        let response: OllamaResponse = serde_json::from_str(&body)
+            .map_err(|e| BackendError::InvalidResponse(e.to_string()))?;
+        if response.done_reason.as_deref() == Some("length") {
+            return Err(BackendError::InvalidResponse(
+                "Response truncated by context length".to_string(),
+            ));
+        }
+        Ok(BackendResponse {
+            text: response.message.content,
+            usage: Usage {
+                prompt_tokens: response.prompt_eval_count,
+                completion_tokens: response.eval_count,
+            },
+        })
+    }
+
+    async fn chat_llama_cpp(
+        &self,
+        prompt: String,
+        user_message: String,
+        schema: Option<serde_json::Value>,
+    ) -> Result<BackendResponse, BackendError> {
+        let request = LlamaCppRequest {
+            model: &self.model,
+            messages: Self::messages(prompt, user_message),
+            stream: false,
+            temperature: self.temperature,
+            response_format: schema
+                .map(|schema| serde_json::json!({ "type": "json_object", "schema": schema })),
+        };
+        let body = self.post("/v1/chat/completions", &request).await?;
+        let response: LlamaCppResponse = serde_json::from_str(&body)
+            .map_err(|e| BackendError::InvalidResponse(e.to_string()))?;
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| BackendError::InvalidResponse("No Content Found".to_string()))?;
+        if choice.finish_reason.as_deref() == Some("length") {
+            return Err(BackendError::InvalidResponse(
+                "Response truncated by context length".to_string(),
+            ));
+        }
+        let usage = response
+            .usage
+            .map(|u| Usage {
+                prompt_tokens: u.prompt_tokens,
+                completion_tokens: u.completion_tokens,
+            })
+            .unwrap_or_default();
+        Ok(BackendResponse {
+            text: choice.message.content,
+            usage,
+        })
+    }
+}
+
+#[async_trait]
+impl TranslationBackend for LocalLlm {
+    fn name(&self) -> &str {
+        match self.server {
+            LocalServer::Ollama => "ollama",
+            LocalServer::LlamaCpp => "llama-cpp",
+        }
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn parameters(&self) -> String {
+        format!(
+            "temperature={:?};num_ctx={:?}",
+            self.temperature, self.context_size
+        )
+    }
+
+    async fn chat(
+        &self,
+        prompt: String,
+        user_message: String,
+    ) -> Result<BackendResponse, BackendError> {
+        match self.server {
+            LocalServer::Ollama => self.chat_ollama(prompt, user_message, None).await,
+            LocalServer::LlamaCpp => self.chat_llama_cpp(prompt, user_message, None).await,
+        }
+    }
+
+    /// Ollama 的 `format` 和 llama.cpp 的 `response_format` 都支持 JSON schema
+    async fn chat_json(
+        &self,
+        prompt: String,
+        user_message: String,
+        schema: serde_json::Value,
+    ) -> Result<BackendResponse, BackendError> {
+        match self.server {
+            LocalServer::Ollama => self.chat_ollama(prompt, user_message, Some(schema)).await,
+            LocalServer::LlamaCpp => {
+                self.chat_llama_cpp(prompt, user_message, Some(schema))
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockServer;
+
+    #[tokio::test]
+    async fn test_chat_ollama() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"model":"qwen2.5:7b","message":{"role":"assistant","content":"你好？"},"done":true,"done_reason":"stop","prompt_eval_count":30,"eval_count":5}"#.to_string(),
+        )])
+        .await;
+        let local = LocalLlm::new(LocalServer::Ollama, "qwen2.5:7b")
+            .with_api_base(&server.url())
+            .with_context_size(Some(8192))
+            .with_keep_alive(Some("10m".to_string()));
+
+        let response = local
+            .chat("system prompt".to_string(), "Hello?".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, "你好？");
+        assert_eq!(response.usage.total_tokens(), 35);
+        let request = &server.requests()[0];
+        assert!(request.starts_with("POST /api/chat"));
+        assert!(!request.to_lowercase().contains("authorization"));
+        assert!(request.contains(r#""stream":false"#));
+        assert!(request.contains(r#""keep_alive":"10m""#));
+        assert!(request.contains(r#""options":{"num_ctx":8192}"#));
+    }
+
+    #[tokio::test]
+    async fn test_chat_ollama_json() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"message":{"role":"assistant","content":"{\"1\":\"你好？\"}"},"done":true,"done_reason":"stop"}"#.to_string(),
+        )])
+        .await;
+        let local = LocalLlm::new(LocalServer::Ollama, "qwen2.5:7b").with_api_base(&server.url());
+
+        let response = local
+            .chat_json(
+                "system prompt".to_string(),
+                "Hello?".to_string(),
+                crate::structured::schema(1),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, r#"{"1":"你好？"}"#);
+        assert!(server.requests()[0].contains(r#""format":{"additionalProperties":false"#));
+    }
+
+    #[tokio::test]
+    async fn test_chat_llama_cpp() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"choices":[{"index":0,"message":{"role":"assistant","content":"你好？"},"finish_reason":"stop"}],"usage":{"prompt_tokens":30,"completion_tokens":5,"total_tokens":35}}"#.to_string(),
+        )])
+        .await;
+        let local = LocalLlm::new(LocalServer::LlamaCpp, "local").with_api_base(&server.url());
+
+        let response = local
+            .chat("system prompt".to_string(), "Hello?".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, "你好？");
+        assert_eq!(response.usage.total_tokens(), 35);
+        assert!(server.requests()[0].starts_with("POST /v1/chat/completions"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_ollama_truncated() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"message":{"role":"assistant","content":"你"},"done":true,"done_reason":"length"}"#
+                .to_string(),
+        )])
+        .await;
+        let local = LocalLlm::new(LocalServer::Ollama, "qwen2.5:7b").with_api_base(&server.url());
+
+        let result = local
+            .chat("system prompt".to_string(), "Hello?".to_string())
+            .await;
+
+        assert!(matches!(result, Err(BackendError::InvalidResponse(_))));
+    }
+}
@@ -1,40 +1,273 @@
-use clap::{Parser, Subcommand};
+use clap::{Args as ClapArgs, Parser, Subcommand};
 use std::path::PathBuf;
-use subtitle_translator_cli::{config::Config, handler::handle_openai_translate};
+use subtitle_translator_cli::{
+    cache::Cache,
+    config::{Config, ConfigOverrides, Provider},
+    formatter::ContextWindow,
+    handler::{handle_dry_run, handle_translate},
+    local::LocalServer,
+    structured::ResponseMode,
+    subtitle_combiner::Layout,
+    writer::OutputFormat,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Config file, overrides the user and project config files
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Named profile from the config files
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    /// Model name, overrides the config files and STC_MODEL
+    #[arg(short, long, global = true)]
+    model: Option<String>,
+    /// API base URL, overrides the config files and STC_API_BASE
+    #[arg(long, global = true)]
+    api_base: Option<String>,
+    /// Directory with prompt templates (prompt.txt, user_message.txt, ...) overriding the built-in ones
+    #[arg(long, global = true)]
+    template_dir: Option<PathBuf>,
+    /// Output subtitle format, defaults to the format of the input file (VTT is output only)
+    #[arg(long, global = true, value_enum)]
+    output_format: Option<OutputFormat>,
+    /// Bilingual layout of the translated and original lines
+    #[arg(long, global = true, value_enum)]
+    layout: Option<Layout>,
+    /// Continue from the checkpoint of an interrupted run (default)
+    #[arg(long, global = true, overrides_with = "no_resume")]
+    resume: bool,
+    /// Ignore any checkpoint and translate every chunk again
+    #[arg(long, global = true)]
+    no_resume: bool,
+    /// Do not read or write the translation cache
+    #[arg(long, global = true)]
+    no_cache: bool,
+    /// Maximum attempts per request, including the first one
+    #[arg(long, global = true)]
+    max_attempts: Option<u32>,
+    /// How the model returns translations: `<T>`-delimited text or a JSON object
+    #[arg(long, global = true, value_enum)]
+    response_mode: Option<ResponseMode>,
+    /// Estimated source tokens per request
+    #[arg(long, global = true)]
+    chunk_tokens: Option<usize>,
+    /// Minimum cues per request, even if the token budget is exceeded
+    #[arg(long, global = true)]
+    min_cues: Option<usize>,
+    /// Maximum cues per request
+    #[arg(long, global = true)]
+    max_cues: Option<usize>,
+    /// Silence in milliseconds that ends a chunk early, once it is half full
+    #[arg(long, global = true)]
+    scene_gap_ms: Option<u64>,
+    /// Re-wrap translated lines to this display width (CJK characters count as 2)
+    #[arg(long, global = true)]
+    max_line_width: Option<usize>,
+    /// Maximum lines per translated cue when re-wrapping
+    #[arg(long, global = true)]
+    max_lines: Option<usize>,
+    /// Context around each request: `full`, `N chunks` or `N tokens` before and after
+    #[arg(long, global = true)]
+    context_window: Option<ContextWindow>,
+    /// Translate sentences split across several cues as a whole, then spread the translation back over the cues
+    #[arg(long, global = true)]
+    merge_sentences: bool,
+    /// Glossary file (.csv, .tsv or .json) with the required translation of names and terms
+    #[arg(long, global = true)]
+    glossary: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
 
+#[derive(ClapArgs, Debug)]
+struct TranslateArgs {
+    #[arg(short)]
+    path: PathBuf,
+    #[arg(short)]
+    source_language: String,
+    #[arg(short)]
+    target_language: String,
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
+    /// Translate with the provider of the selected profile
+    Translate {
+        #[command(flatten)]
+        args: TranslateArgs,
+        #[arg(long, value_enum)]
+        provider: Option<Provider>,
+    },
     Openai {
-        #[arg(short)]
-        path: PathBuf,
-        #[arg(short)]
-        source_language: String,
-        #[arg(short)]
-        target_language: String,
+        #[command(flatten)]
+        args: TranslateArgs,
+    },
+    Anthropic {
+        #[command(flatten)]
+        args: TranslateArgs,
+    },
+    /// Translate with a local Ollama or llama.cpp server
+    Local {
+        #[command(flatten)]
+        args: TranslateArgs,
+        #[arg(long, value_enum, default_value_t = LocalServer::Ollama)]
+        server: LocalServer,
+        /// Context size in tokens (Ollama only)
+        #[arg(long)]
+        num_ctx: Option<u32>,
+        /// How long the model stays loaded, e.g. 5m (Ollama only)
+        #[arg(long)]
+        keep_alive: Option<String>,
+    },
+    /// Run the whole pipeline with an offline echo backend, without spending tokens
+    DryRun {
+        #[command(flatten)]
+        args: TranslateArgs,
+    },
+    /// Show the effective configuration and where each value came from
+    Config,
+    /// Inspect or clean the translation cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Show the number of cached responses and their size
+    Stats,
+    /// Delete cached responses
+    Prune {
+        /// Only delete responses older than this many days
+        #[arg(long)]
+        older_than: Option<u64>,
+    },
+}
+
+fn run_cache_command(action: CacheCommand, config: &Config) -> Result<(), String> {
+    let dir = config
+        .cache_dir
+        .clone()
+        .ok_or("无法确定缓存目录，请在配置文件中设置 cache_dir")?;
+    let cache = Cache::new(dir);
+    match action {
+        CacheCommand::Stats => {
+            let stats = cache.stats().map_err(|e| e.to_string())?;
+            println!("{:<8} = {}", "dir", cache.dir().display());
+            println!("{:<8} = {}", "entries", stats.entries);
+            println!("{:<8} = {} KiB", "size", stats.bytes.div_ceil(1024));
+        }
+        CacheCommand::Prune { older_than } => {
+            let older_than =
+                older_than.map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60));
+            let removed = cache.prune(older_than).map_err(|e| e.to_string())?;
+            println!("Removed {} cached responses", removed);
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
-    if let Err(e) = Config::init() {
-        eprintln!("配置初始化失败: {}", e);
-        std::process::exit(1);
-    }
     let args = Args::parse();
-    match args.command {
-        Command::Openai {
-            path,
-            source_language,
-            target_language,
-        } => handle_openai_translate(path, source_language, target_language)
-            .await
-            .expect("Failed to handle OpenAI translation"),
+    let mut overrides = ConfigOverrides {
+        config_path: args.config,
+        profile: args.profile,
+        api_base: args.api_base,
+        model: args.model,
+        template_dir: args.template_dir,
+        output_format: args.output_format,
+        layout: args.layout,
+        resume: match (args.resume, args.no_resume) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        cache: args.no_cache.then_some(false),
+        max_attempts: args.max_attempts,
+        response_mode: args.response_mode,
+        chunk_tokens: args.chunk_tokens,
+        min_cues: args.min_cues,
+        max_cues: args.max_cues,
+        scene_gap_ms: args.scene_gap_ms,
+        max_line_width: args.max_line_width,
+        max_lines: args.max_lines,
+        context_window: args.context_window,
+        merge_sentences: args.merge_sentences.then_some(true),
+        glossary: args.glossary,
+        ..Default::default()
+    };
+    let mut cache_command = None;
+    let translate_args = match args.command {
+        Command::Translate { args, provider } => {
+            overrides.provider = provider;
+            Some(args)
+        }
+        Command::Openai { args } => {
+            overrides.provider = Some(Provider::OpenAI);
+            Some(args)
+        }
+        Command::Anthropic { args } => {
+            overrides.provider = Some(Provider::Anthropic);
+            Some(args)
+        }
+        Command::Local {
+            args,
+            server,
+            num_ctx,
+            keep_alive,
+        } => {
+            overrides.provider = Some(match server {
+                LocalServer::Ollama => Provider::Ollama,
+                LocalServer::LlamaCpp => Provider::LlamaCpp,
+            });
+            overrides.context_size = num_ctx;
+            overrides.keep_alive = keep_alive;
+            Some(args)
+        }
+        Command::DryRun { args } => {
+            overrides.provider = Some(Provider::Echo);
+            Some(args)
+        }
+        Command::Config => None,
+        Command::Cache { action } => {
+            cache_command = Some(action);
+            None
+        }
+    };
+    let config = match Config::load(&overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("配置初始化失败: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let Some(TranslateArgs {
+        path,
+        source_language,
+        target_language,
+    }) = translate_args
+    else {
+        match cache_command {
+            Some(action) => {
+                if let Err(e) = run_cache_command(action, &config) {
+                    eprintln!("缓存操作失败: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            None => println!("{}", config.describe()),
+        }
+        return;
+    };
+    let result = match config.provider {
+        Provider::Echo => handle_dry_run(path, source_language, target_language, &config).await,
+        _ => handle_translate(path, source_language, target_language, &config).await,
+    };
+    if let Err(e) = result {
+        eprintln!("翻译失败: {}", e);
+        std::process::exit(1);
     }
 }
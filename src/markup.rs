@@ -0,0 +1,151 @@
+//! 替换字幕中的格式标签，避免模型删除、翻译或挪动它们
+//!
+//! `<i>`、`<font color=...>` 等 HTML 标签和 `{\an8}` 等 ASS 覆盖标签在翻译前
+//! 替换为 `[[1]]`、`[[2]]` 这样的占位符，合并译文时再换回原来的标签。
+//! 换行标签 `<nl>` 不受影响
+
+/// 第 `number` 个标签的占位符，从 1 开始编号
+fn placeholder(number: usize) -> String {
+    format!("[[{}]]", number)
+}
+
+/// 从 `text` 开头取出一个标签，返回标签的字节长度
+fn tag_len(text: &str) -> Option<usize> {
+    let close = match text.as_bytes().first()? {
+        b'<' => '>',
+        b'{' => '}',
+        _ => return None,
+    };
+    if close == '>' {
+        // 只把 `<i>`、`</font>` 这样以字母开头的内容视为标签，`a < b` 不是标签
+        let name = text[1..].trim_start_matches('/');
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || text.starts_with("<nl>") {
+            return None;
+        }
+    }
+    text.find(close).map(|end| end + 1)
+}
+
+/// 把标签替换为占位符，返回替换后的文本和按编号排列的标签
+pub fn protect(text: &str) -> (String, Vec<String>) {
+    let mut protected = String::with_capacity(text.len());
+    let mut tags = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['<', '{']) {
+        protected.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match tag_len(rest) {
+            Some(len) => {
+                tags.push(rest[..len].to_string());
+                protected.push_str(&placeholder(tags.len()));
+                rest = &rest[len..];
+            }
+            None => {
+                protected.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    protected.push_str(rest);
+    (protected, tags)
+}
+
+/// 文本中是否有占位符
+pub fn has_placeholders(text: &str) -> bool {
+    text.contains("[[")
+}
+
+/// 文本是否只由占位符和空白组成
+fn only_placeholders(text: &str) -> bool {
+    let mut rest = text.trim_start();
+    while let Some((_, after)) = rest
+        .strip_prefix("[[")
+        .and_then(|inner| inner.split_once("]]"))
+    {
+        rest = after.trim_start();
+    }
+    rest.is_empty()
+}
+
+/// 把占位符换回标签
+///
+/// 译文中丢失的占位符按原文中的位置补回：原文开头的标签放在开头，其余的放在结尾。
+/// 返回恢复后的文本和丢失的占位符编号
+pub fn restore(text: &str, original: &str, tags: &[String]) -> (String, Vec<usize>) {
+    let mut restored = text.to_string();
+    let mut lost = Vec::new();
+    let mut prefix = String::new();
+    let mut suffix = String::new();
+    for (index, tag) in tags.iter().enumerate() {
+        let placeholder = placeholder(index + 1);
+        if restored.contains(&placeholder) {
+            restored = restored.replace(&placeholder, tag);
+            continue;
+        }
+        lost.push(index + 1);
+        let leading = original
+            .find(&placeholder)
+            .is_some_and(|position| only_placeholders(&original[..position]));
+        if leading {
+            prefix.push_str(tag);
+        } else {
+            suffix.push_str(tag);
+        }
+    }
+    (format!("{}{}{}", prefix, restored, suffix), lost)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_protect() {
+        let (text, tags) = protect(
+            "{\\an8}<i>Magic</i> <font color=\"#ff0000\">does</font> exist, a < b.<nl>{\\i1}Yes",
+        );
+
+        assert_eq!(
+            text,
+            "[[1]][[2]]Magic[[3]] [[4]]does[[5]] exist, a < b.<nl>[[6]]Yes"
+        );
+        assert_eq!(
+            tags,
+            [
+                "{\\an8}",
+                "<i>",
+                "</i>",
+                "<font color=\"#ff0000\">",
+                "</font>",
+                "{\\i1}"
+            ]
+        );
+        assert_eq!(
+            protect("He swore an oath."),
+            ("He swore an oath.".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn test_restore() {
+        let source = "{\\an8}<i>Magic does exist.</i>";
+        let (original, tags) = protect(source);
+
+        assert_eq!(
+            restore(&original, &original, &tags),
+            (source.to_string(), vec![])
+        );
+        assert_eq!(
+            restore("[[1]][[2]]魔法确实存在。[[3]]", &original, &tags),
+            ("{\\an8}<i>魔法确实存在。</i>".to_string(), vec![])
+        );
+        assert_eq!(
+            restore("魔法确实存在。[[3]]", &original, &tags),
+            ("{\\an8}<i>魔法确实存在。</i>".to_string(), vec![1, 2])
+        );
+        assert_eq!(
+            restore("[[2]]魔法确实存在。", &original, &tags),
+            ("{\\an8}<i>魔法确实存在。</i>".to_string(), vec![1, 3])
+        );
+    }
+}
@@ -1,12 +1,41 @@
-use std::path::PathBuf;
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
+use async_trait::async_trait;
 use subparse::SubtitleEntry;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
 
 use crate::{
-    formatter::Formatter, parse::parse_file, subtitle_extractor::SubtitleExtractor,
-    text_splitter::TextSplitter, translator::Translator,
+    backend::{BackendError, BackendResponse, TranslationBackend, Usage},
+    formatter::Formatter,
+    parse::parse_file,
+    subtitle_extractor::SubtitleExtractor,
+    text_splitter::TextSplitter,
+    translator::Translator,
 };
 
+/// 为测试新建一个独立的临时目录，目录名包含进程号和计数，并行的测试不会互相影响
+pub fn temp_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "stc_test_{}_{}_{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+    dir
+}
+
 pub struct Mock {
     pub path: PathBuf,
     pub subtitle_entries: Vec<SubtitleEntry>,
@@ -16,6 +45,12 @@ pub struct Mock {
     pub translator: Translator,
 }
 
+impl Default for Mock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Mock {
     pub fn new() -> Self {
         let path = PathBuf::from("test.srt");
@@ -24,8 +59,10 @@ impl Mock {
             SubtitleExtractor::extractor(&subtitle_entries).expect("Failed to extract subtitle");
         let text_splitter =
             TextSplitter::split_text(&subtitle_extractor.text_info).expect("Failed to split text");
-        let formatter = crate::formatter::Formatter::format(1, &text_splitter.split_result);
-        let translator = Translator::new();
+        let formatter = crate::formatter::Formatter::format(1, &text_splitter.split_result)
+            .expect("Failed to format");
+        let templates = crate::template::load_templates(None).expect("Failed to load templates");
+        let translator = Translator::new(Arc::new(MockBackend::new()), Arc::new(templates));
         // translator.translated_result = String::from("【思考】本轮是对源文本的第一轮直译，目标是忠实地将内容逐句翻译成中文，保证信息的完整性。\n\n【翻译】\n在哪里见鬼<nl>你的所谓魔法师？<T>他会来的，兰斯洛特。<T>他发誓过。<T>他们正在准备第二波攻击。<T>冲锋！<T>这……这就是终结的模样。<T>你的所谓魔法师，<nl>梅林，帮不了我们。<T>我们人数以百对一。<T>我们该如何摆脱这个？<T>他承诺会有武器，<nl>一种强大的武器。<T>亚瑟，你是我的国王。<T>我愿为你献出生命，<T>但这个梅林根本不是巫师。<T>他是个无用的酒鬼！<T>哦，天啊，我喝醉了！<T>再来一口。<T><i>魔法确实存在。</i><T><i>它很久以前被发现，</i><T><i>就在一艘坠毁的外星飞船里。</i><T>你好？\n\n【思考】第二轮意译是在第一轮直译的基础上，使语言更通顺流畅，同时保留原有的信息。\n\n【翻译】\n你的所谓魔法师到底在哪里？<T>他会来的，兰斯洛特。<T>他发过誓。<T>他们在准备第二波攻击。<T>冲！<T>这……这就是末日的样子。<T>你的所谓魔法师，<nl>梅林，无法帮助我们。<T>我们的敌人多我们100倍！<T>我们怎么才能脱身？<T>他答应过会给我们一个武器，<nl>一件强大的武器。<T>亚瑟，你是我的国王。<T>我愿意为你献出生命，<T>但这个梅林根本不是个巫师。<T>他只是个无用的酒徒！<T>哦，天啊，我醉得不轻！<T>再来一口。<T><i>魔法确实存在。</i><T><i>很久以前被发现于，</i><T><i>一艘坠毁的外星飞船。</i><T>你好？\n\n【思考】本轮反思主要是检查意译的准确性、流畅性、风格和术语使用。\n\n*准确性*：翻译基本准确，没有明显遗漏或错误，但可以进一步简化某些表达。  \n*流畅性*：整体表达较流畅，但个别句子可以更口语化。  \n*风格*：基本符合源文本的风格，但在一些情感的传达上可以稍作增强。  \n*术语*：术语使用较为一致，注意“巫师”和“魔法师”的调用。\n\n【建议】\n- 确保“魔法师”和“巫师”的用词统一性。\n- 在某些表达上更自然口语化，例如“再来一口”可以轻松一点。\n- 在表达情感反应时，让角色的挫折感更明显。\n\n【思考】本轮提升根据建议对译文进行修改，提高语言的流畅度和情感的传达。\n\n``` \n你的所谓魔法师到底在哪里？<T>他马上就会来，兰斯洛特。<T>他发过誓。<T>他们在准备第二波攻击。<T>冲！<T>这……这就是末日的样子。<T>你的所谓魔法师，<nl>梅林，根本帮不了我们。<T>我们的人数多达敌方的100倍！<T>我们该怎么脱身？<T>他承诺会给我们一个武器，<nl>一件强大的武器。<T>亚瑟，你是我的国王。<T>我愿为你献出生命，<T>但是这个梅林根本不是个巫师。<T>他只不过是个废物酒鬼！<T>哦，天啊，我醉得不轻！<T>再来一口。<T><i>魔法确实存在。</i><T><i>很久以前就在，</i><T><i>一艘坠毁的外星飞船里被发现。</i><T>你好？\n```");
         // let translator = translator.format_translated_result();
         Self {
@@ -38,3 +75,144 @@ impl Mock {
         }
     }
 }
+
+/// 测试用的翻译后端
+///
+/// 按顺序返回预设的回复或错误，预设回复用完后原样返回 `<TRANSLATE_THIS>` 中的文本
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    responses: Mutex<VecDeque<Result<String, BackendError>>>,
+    requests: Mutex<Vec<String>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_responses(responses: Vec<String>) -> Self {
+        Self::with_results(responses.into_iter().map(Ok).collect())
+    }
+
+    pub fn with_results(results: Vec<Result<String, BackendError>>) -> Self {
+        Self {
+            responses: Mutex::new(results.into()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 获取收到的所有用户消息
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// 原样返回最后一段 `<TRANSLATE_THIS>` 标签内的文本
+    fn echo(user_message: &str) -> String {
+        let chunk = user_message
+            .rsplit_once("<TRANSLATE_THIS>")
+            .and_then(|(_, rest)| rest.split_once("</TRANSLATE_THIS>"))
+            .map(|(chunk, _)| chunk.trim())
+            .unwrap_or_default();
+        format!("```\n{}\n```", chunk)
+    }
+}
+
+#[async_trait]
+impl TranslationBackend for MockBackend {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    async fn chat(
+        &self,
+        _prompt: String,
+        user_message: String,
+    ) -> Result<BackendResponse, BackendError> {
+        self.requests.lock().unwrap().push(user_message.clone());
+        let text = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Ok(Self::echo(&user_message)))?;
+        Ok(BackendResponse {
+            text,
+            usage: Usage::default(),
+        })
+    }
+}
+
+/// 测试用的本地 HTTP 服务，按顺序返回预设的 `(状态码, 响应体)`
+pub struct MockServer {
+    addr: std::net::SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    pub async fn start(responses: Vec<(u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let addr = listener.local_addr().expect("Failed to get local address");
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let request = Self::read_request(&mut stream).await;
+                recorded.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        Self { addr, requests }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// 获取收到的所有原始请求
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> String {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            buffer.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buffer);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let content_length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= content_length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&buffer).to_string()
+    }
+}
@@ -1,88 +1,265 @@
-use async_openai::{
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
-    },
-    Client,
+use async_openai::types::{
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionRequestArgs, CreateChatCompletionResponse, ResponseFormat,
+    ResponseFormatJsonSchema,
 };
+use async_trait::async_trait;
+use serde::Deserialize;
 
-use crate::CONFIG;
+use crate::backend::{BackendError, BackendResponse, TranslationBackend, Usage};
 
+pub const DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+pub const DEFAULT_MODEL: &str = "gpt-4o-mini";
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    message: String,
+    code: Option<String>,
+}
+
+/// OpenAI 兼容的 Chat Completions 后端
+///
+/// 直接发送请求而不使用 `async_openai::Client`，以便读取状态码和限流响应头
 #[derive(Debug)]
 pub struct OpenAI {
+    api_key: String,
+    api_base: String,
     model: String,
-    client: Client<OpenAIConfig>,
+    temperature: Option<f32>,
+    client: reqwest::Client,
 }
 impl OpenAI {
-    pub fn new() -> Self {
-        // 使用锁来访问全局配置
-        let config = CONFIG.lock().unwrap();
-        let api_key = config.api_key.clone();
-        let api_base = config.api_base.clone();
-        let model = config.model.clone();
-        // eprintln!("API_KEY: {}", api_key);
-        // eprintln!("API_BASE: {}", api_base);
-
-        let config = OpenAIConfig::new()
-            .with_api_key(&api_key)
-            .with_api_base(&api_base);
-        let client = Client::with_config(config);
-        Self { model, client }
+    pub fn new(api_key: &str, model: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            api_base: DEFAULT_API_BASE.to_string(),
+            model: model.to_string(),
+            temperature: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// 把错误响应转换为 [`BackendError`]，额度用完的 429 不再重试
+    fn parse_error(
+        status: reqwest::StatusCode,
+        headers: &reqwest::header::HeaderMap,
+        body: String,
+    ) -> BackendError {
+        match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(e) if e.error.code.as_deref() == Some("insufficient_quota") => {
+                BackendError::InvalidRequest(e.error.message)
+            }
+            Ok(e) => BackendError::http(status, headers, e.error.message),
+            Err(_) => BackendError::http(status, headers, body),
+        }
     }
-    pub async fn chat(
+
+    /// 发送请求，`response_format` 为 `None` 时返回普通文本
+    async fn send(
         &self,
         prompt: String,
         user_message: String,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let request = CreateChatCompletionRequestArgs::default()
+        response_format: Option<ResponseFormat>,
+    ) -> Result<BackendResponse, BackendError> {
+        let mut request = CreateChatCompletionRequestArgs::default();
+        if let Some(temperature) = self.temperature {
+            request.temperature(temperature);
+        }
+        if let Some(response_format) = response_format {
+            request.response_format(response_format);
+        }
+        let request = request
             .max_tokens(4_000u32)
             .model(self.model.clone())
             .messages([
                 ChatCompletionRequestSystemMessageArgs::default()
                     .content(prompt)
-                    .build()?
+                    .build()
+                    .map_err(|e| BackendError::InvalidRequest(e.to_string()))?
                     .into(),
                 ChatCompletionRequestUserMessageArgs::default()
                     .content(user_message)
-                    .build()?
+                    .build()
+                    .map_err(|e| BackendError::InvalidRequest(e.to_string()))?
                     .into(),
             ])
-            .build()?;
-        let response = self.client.chat().create(request).await?;
-        Ok(response.choices[0]
-            .message
-            .content
-            .clone()
-            .expect("No Content Found"))
+            .build()
+            .map_err(|e| BackendError::InvalidRequest(e.to_string()))?;
+        let response = self
+            .client
+            .post(format!("{}/chat/completions", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| BackendError::RequestError(e.to_string()))?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| BackendError::RequestError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(Self::parse_error(status, &headers, body));
+        }
+        let response: CreateChatCompletionResponse = serde_json::from_str(&body)
+            .map_err(|e| BackendError::InvalidResponse(e.to_string()))?;
+        let usage = response
+            .usage
+            .map(|u| Usage {
+                prompt_tokens: u.prompt_tokens,
+                completion_tokens: u.completion_tokens,
+            })
+            .unwrap_or_default();
+        let text = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| BackendError::InvalidResponse("No Content Found".to_string()))?;
+        Ok(BackendResponse { text, usage })
+    }
+}
+
+#[async_trait]
+impl TranslationBackend for OpenAI {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn parameters(&self) -> String {
+        format!("temperature={:?}", self.temperature)
+    }
+
+    async fn chat(
+        &self,
+        prompt: String,
+        user_message: String,
+    ) -> Result<BackendResponse, BackendError> {
+        self.send(prompt, user_message, None).await
+    }
+
+    /// 使用 Structured Outputs 约束回复的 JSON 格式
+    async fn chat_json(
+        &self,
+        prompt: String,
+        user_message: String,
+        schema: serde_json::Value,
+    ) -> Result<BackendResponse, BackendError> {
+        let response_format = ResponseFormat::JsonSchema {
+            json_schema: ResponseFormatJsonSchema {
+                description: None,
+                name: "translations".to_string(),
+                schema: Some(schema),
+                strict: Some(true),
+            },
+        };
+        self.send(prompt, user_message, Some(response_format)).await
     }
 }
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::mock::MockServer;
+
     #[tokio::test]
     pub async fn test() -> Result<(), Box<dyn std::error::Error>> {
-        let openai = super::OpenAI::new();
-        let request = async_openai::types::CreateChatCompletionRequestArgs::default()
-            .max_tokens(40u32)
-            .model("gpt-4o-mini")
-            .messages([
-                async_openai::types::ChatCompletionRequestUserMessageArgs::default()
-                    .content("Hi! Are you ChatGPT?")
-                    .build()?
-                    .into(),
-            ])
-            .build()?;
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"id":"chatcmpl-1","object":"chat.completion","created":0,"model":"gpt-4o-mini","choices":[{"index":0,"message":{"role":"assistant","content":"Yes, I am."},"finish_reason":"stop"}],"usage":{"prompt_tokens":12,"completion_tokens":4,"total_tokens":16}}"#.to_string(),
+        )])
+        .await;
+        let openai = OpenAI::new("test-key", DEFAULT_MODEL).with_api_base(&server.url());
 
-        let response = openai.client.chat().create(request).await?;
-
-        println!("\nResponse:\n");
-        for choice in response.choices {
-            println!(
-                "{}: Role: {}  Content: {:?}",
-                choice.index, choice.message.role, choice.message.content
-            );
-        }
+        let response = openai
+            .chat(
+                "You are ChatGPT.".to_string(),
+                "Hi! Are you ChatGPT?".to_string(),
+            )
+            .await?;
 
+        assert_eq!(response.text, "Yes, I am.");
+        assert_eq!(response.usage.total_tokens(), 16);
+        let requests = server.requests();
+        assert!(requests[0].starts_with("POST /chat/completions"));
+        assert!(requests[0].contains("Hi! Are you ChatGPT?"));
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_chat_json() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"id":"chatcmpl-1","object":"chat.completion","created":0,"model":"gpt-4o-mini","choices":[{"index":0,"message":{"role":"assistant","content":"{\"1\":\"你好\"}"},"finish_reason":"stop"}]}"#.to_string(),
+        )])
+        .await;
+        let openai = OpenAI::new("test-key", DEFAULT_MODEL).with_api_base(&server.url());
+
+        let response = openai
+            .chat_json(
+                "prompt".to_string(),
+                "message".to_string(),
+                crate::structured::schema(1),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, r#"{"1":"你好"}"#);
+        let request = &server.requests()[0];
+        assert!(request.contains(
+            r#""response_format":{"type":"json_schema","json_schema":{"name":"translations""#
+        ));
+        assert!(request.contains(r#""strict":true"#));
+    }
+
+    #[tokio::test]
+    async fn test_error_classification() {
+        let server = MockServer::start(vec![
+            (
+                503,
+                r#"{"error":{"message":"The server is overloaded","type":"server_error","code":null}}"#.to_string(),
+            ),
+            (
+                429,
+                r#"{"error":{"message":"You exceeded your current quota","type":"insufficient_quota","code":"insufficient_quota"}}"#.to_string(),
+            ),
+        ])
+        .await;
+        let openai = OpenAI::new("test-key", DEFAULT_MODEL).with_api_base(&server.url());
+
+        let overloaded = openai
+            .chat("prompt".to_string(), "message".to_string())
+            .await
+            .unwrap_err();
+        let quota = openai
+            .chat("prompt".to_string(), "message".to_string())
+            .await
+            .unwrap_err();
+
+        assert!(overloaded.is_retryable());
+        assert!(overloaded.to_string().contains("The server is overloaded"));
+        assert!(matches!(quota, BackendError::InvalidRequest(_)));
+        assert!(!quota.is_retryable());
+        assert!(server.requests()[0].contains("authorization: Bearer test-key"));
+    }
 }
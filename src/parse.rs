@@ -1,6 +1,10 @@
-use std::io;
 use std::path::Path;
-use subparse::{get_subtitle_format, parse_str, SubtitleEntry};
+use subparse::{get_subtitle_format, parse_str, SubtitleEntry, SubtitleFile, SubtitleFormat};
+
+use crate::error::{Result, StcError};
+
+/// MicroDVD 等按帧计时的格式使用的帧率
+pub const DEFAULT_FPS: f64 = 25.0;
 
 /// 从文件中读取内容到String
 ///
@@ -8,11 +12,11 @@ use subparse::{get_subtitle_format, parse_str, SubtitleEntry};
 /// * `path` - 要读取的文件路径
 ///
 /// # Returns
-/// * `Result<String, io::Error>` - 成功返回文件内容，失败返回IO错误
+/// * `Result<String>` - 成功返回文件内容，失败返回 `StcError::IoError`
 ///
 /// # Errors
 /// 当文件不存在或无法读取时会返回错误
-pub fn read_file(path: &Path) -> Result<String, io::Error> {
+pub fn read_file(path: &Path) -> Result<String> {
     use std::io::Read;
     let mut file = std::fs::File::open(path)?;
     let mut s = String::new();
@@ -20,37 +24,88 @@ pub fn read_file(path: &Path) -> Result<String, io::Error> {
     Ok(s)
 }
 
-/// 解析字幕文件并返回字幕条目列表
-///
-/// # Arguments
-/// * `path` - 字幕文件路径
-///
-/// # Returns
-/// * `Result<Vec<SubtitleEntry>, Box<dyn Error>>` - 成功返回字幕条目列表，失败返回错误
+/// 解析字幕文件，返回保留了原格式信息的字幕文件
 ///
 /// # Errors
 /// - 文件读取错误
 /// - 未知的字幕格式
 /// - 解析错误
-pub fn parse_file(path: &Path) -> Result<Vec<SubtitleEntry>, Box<dyn std::error::Error>> {
+pub fn parse_subtitle_file(path: &Path) -> Result<SubtitleFile> {
     let file_content = read_file(path)?;
 
     let format = match get_subtitle_format(path.extension(), file_content.as_bytes()) {
         Some(f) => f,
-        None => return Err("Unknown subtitle format".into()),
+        None if file_content
+            .trim_start_matches('\u{feff}')
+            .starts_with("WEBVTT") =>
+        {
+            return Err(StcError::ParseError(
+                "WebVTT subtitles can only be written, not read".to_string(),
+            ))
+        }
+        None => return Err(StcError::ParseError("Unknown subtitle format".to_string())),
     };
 
-    let subtitle_file = match parse_str(format, &file_content, 25.0) {
-        Ok(f) => f,
-        Err(e) => return Err(format!("Failed to parse subtitle: {:?}", e).into()),
+    let fps = match format {
+        SubtitleFormat::MicroDVD => microdvd_fps(&file_content).unwrap_or(DEFAULT_FPS),
+        _ => DEFAULT_FPS,
     };
+    match parse_str(format, &file_content, fps) {
+        Ok(f) => Ok(f),
+        Err(e) => Err(StcError::ParseError(format!(
+            "Failed to parse subtitle: {:?}",
+            e
+        ))),
+    }
+}
 
-    let subtitle_entries = match subtitle_file.get_subtitle_entries() {
-        Ok(entries) => entries,
-        Err(e) => return Err(format!("Failed to get subtitle entries: {:?}", e).into()),
-    };
+/// MicroDVD 文件第一行 `{1}{1}23.976` 中声明的帧率
+fn microdvd_fps(content: &str) -> Option<f64> {
+    let first = content.trim_start_matches('\u{feff}').lines().next()?;
+    ["{1}{1}", "{0}{0}"]
+        .iter()
+        .find_map(|header| first.strip_prefix(header))
+        .and_then(|fps| fps.trim().parse().ok())
+        .filter(|fps: &f64| *fps > 0.0)
+}
+
+/// 从字幕文件中取出字幕条目列表
+///
+/// MicroDVD 的帧率行不是字幕，它的文本为 `None`
+///
+/// # Errors
+/// 字幕文件无法转换为条目时返回 `StcError::ParseError`
+pub fn subtitle_entries(subtitle_file: &SubtitleFile) -> Result<Vec<SubtitleEntry>> {
+    let mut entries = subtitle_file
+        .get_subtitle_entries()
+        .map_err(|e| StcError::ParseError(format!("Failed to get subtitle entries: {:?}", e)))?;
+    if let (SubtitleFile::MicroDVDFile(_), Some(first)) = (subtitle_file, entries.first_mut()) {
+        let is_fps = first.timespan.start == first.timespan.end
+            && first
+                .line
+                .as_deref()
+                .is_some_and(|text| text.trim().parse::<f64>().is_ok());
+        if is_fps {
+            first.line = None;
+        }
+    }
+    Ok(entries)
+}
 
-    Ok(subtitle_entries)
+/// 解析字幕文件并返回字幕条目列表
+///
+/// # Arguments
+/// * `path` - 字幕文件路径
+///
+/// # Returns
+/// * `Result<Vec<SubtitleEntry>>` - 成功返回字幕条目列表，失败返回错误
+///
+/// # Errors
+/// - 文件读取错误
+/// - 未知的字幕格式
+/// - 解析错误
+pub fn parse_file(path: &Path) -> Result<Vec<SubtitleEntry>> {
+    subtitle_entries(&parse_subtitle_file(path)?)
 }
 
 #[cfg(test)]
@@ -77,4 +132,22 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_microdvd_fps() {
+        let content = "{1}{1}23.976\n{0}{24}{y:i}Hello|World\n";
+        let file = parse_str(
+            SubtitleFormat::MicroDVD,
+            content,
+            microdvd_fps(content).unwrap(),
+        )
+        .unwrap();
+
+        let entries = subtitle_entries(&file).unwrap();
+
+        assert_eq!(microdvd_fps("{0}{24}Hello"), None);
+        assert_eq!(entries[0].line, None);
+        assert_eq!(entries[1].timespan.end.msecs(), 1001);
+        assert_eq!(entries[1].line.as_deref(), Some("Hello"));
+    }
 }
@@ -0,0 +1,220 @@
+use std::ops::Range;
+
+use crate::{
+    backend::Usage,
+    error::Result,
+    formatter::{ContextWindow, Formatter},
+    translator::Translator,
+};
+
+const DELIMITER: &str = "<T>";
+
+fn segment_count(text: &str) -> usize {
+    text.split(DELIMITER).count()
+}
+
+/// 把范围分成前后两半
+fn halves(range: Range<usize>) -> [Range<usize>; 2] {
+    let middle = range.start + range.len() / 2;
+    [range.start..middle, middle..range.end]
+}
+
+/// 翻译第 `index` 个分块，保证译文的分段数量与原文一致
+///
+/// 分段数量对不上时依次：
+/// 1. 追加 `repair_message.txt` 中更严格的说明重新请求整个分块
+/// 2. 把分块二分成更小的组分别翻译，仍然对不上的组继续二分
+/// 3. 分到只剩一帧字幕时，把这一帧的全部译文合并为一段
+///
+/// 每次请求只带 `window` 以内的上下文，返回用 `<T>` 连接的译文和所有请求累计的 token。
+/// `lines` 是每个分段在原字幕文件中对应的条目范围，用于日志中的行号
+pub async fn translate_chunk(
+    translator: &mut Translator,
+    source_text_chunks: &[String],
+    lines: &[Range<usize>],
+    index: usize,
+    window: ContextWindow,
+    source_language: &str,
+    target_language: &str,
+) -> Result<(String, Usage)> {
+    let expected = source_text_chunks
+        .get(index)
+        .map_or(0, |chunk| segment_count(chunk));
+    let formatter = Formatter::format_segments(index, source_text_chunks, 0..expected, window)?;
+    let mut usage = Usage::default();
+
+    translator
+        .translate(
+            source_language,
+            target_language,
+            formatter.tagged_text.clone(),
+            formatter.chunk_to_translate.clone(),
+        )
+        .await?;
+    usage += translator.usage;
+    let reason = match translator.translated_segments() {
+        Ok(segments) => return Ok((segments.join(DELIMITER), usage)),
+        Err(reason) => reason,
+    };
+
+    // 日志中的行号按原字幕文件计算，没有对应范围时按分段的顺序计算
+    let offset = source_text_chunks[..index]
+        .iter()
+        .map(|chunk| segment_count(chunk))
+        .sum::<usize>();
+    let line_range = |range: Range<usize>| {
+        let (first, last) = (offset + range.start, offset + range.end - 1);
+        (
+            lines.get(first).map_or(first, |lines| lines.start) + 1,
+            lines.get(last).map_or(last + 1, |lines| lines.end),
+        )
+    };
+    let (first_line, last_line) = line_range(0..expected);
+    eprintln!(
+        "Line {} to {}: {}, retrying with a stricter prompt",
+        first_line, last_line, reason
+    );
+    translator
+        .translate_strict(
+            source_language,
+            target_language,
+            formatter.tagged_text,
+            formatter.chunk_to_translate,
+        )
+        .await?;
+    usage += translator.usage;
+    if let Ok(segments) = translator.translated_segments() {
+        return Ok((segments.join(DELIMITER), usage));
+    }
+    if expected == 1 {
+        return Ok((translator.translated_text_lossy(), usage));
+    }
+
+    let mut translated = vec![String::new(); expected];
+    // 后进先出，先处理前半部分
+    let mut pending: Vec<Range<usize>> = halves(0..expected).into_iter().rev().collect();
+    while let Some(range) = pending.pop() {
+        let (first_line, last_line) = line_range(range.clone());
+        eprintln!(
+            "Line {} to {}: translating separately",
+            first_line, last_line
+        );
+        let formatter =
+            Formatter::format_segments(index, source_text_chunks, range.clone(), window)?;
+        translator
+            .translate_strict(
+                source_language,
+                target_language,
+                formatter.tagged_text,
+                formatter.chunk_to_translate,
+            )
+            .await?;
+        usage += translator.usage;
+        if let Ok(segments) = translator.translated_segments() {
+            translated[range].clone_from_slice(&segments);
+        } else if range.len() == 1 {
+            translated[range.start] = translator.translated_text_lossy();
+        } else {
+            pending.extend(halves(range).into_iter().rev());
+        }
+    }
+    Ok((translated.join(DELIMITER), usage))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mock::MockBackend, structured::ResponseMode};
+    use std::sync::Arc;
+
+    fn translator(backend: Arc<MockBackend>) -> Translator {
+        let templates = crate::template::load_templates(None).expect("Failed to load templates");
+        Translator::new(backend, Arc::new(templates))
+    }
+
+    #[tokio::test]
+    async fn test_strict_retry() {
+        let backend = Arc::new(MockBackend::with_responses(vec![
+            "```\n一二\n```".to_string(),
+            "```\n一<T>二\n```".to_string(),
+        ]));
+        let chunks = vec!["one<T>two".to_string()];
+
+        let (text, _) = translate_chunk(
+            &mut translator(backend.clone()),
+            &chunks,
+            &[],
+            0,
+            ContextWindow::Full,
+            "en",
+            "zh",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(text, "一<T>二");
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("共有2帧字幕"));
+    }
+
+    #[tokio::test]
+    async fn test_bisect_and_merge_lines() {
+        let backend = Arc::new(MockBackend::with_responses(vec![
+            "```\n一二三四\n```".to_string(),
+            "```\n一二三四\n```".to_string(),
+            // 前半部分一次成功
+            "```\n一<T>二\n```".to_string(),
+            // 后半部分继续二分，最后一帧多出的 <T> 被合并
+            "```\n三四\n```".to_string(),
+            "```\n三\n```".to_string(),
+            "```\n四<T>五\n```".to_string(),
+        ]));
+        let chunks = vec!["zero".to_string(), "one<T>two<T>three<T>four".to_string()];
+
+        let (text, _) = translate_chunk(
+            &mut translator(backend.clone()),
+            &chunks,
+            &[],
+            1,
+            ContextWindow::Full,
+            "en",
+            "zh",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(text, "一<T>二<T>三<T>四 五");
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 6);
+        assert!(requests[2].contains("<TRANSLATE_THIS>one<T>two</TRANSLATE_THIS>"));
+        assert!(requests[5].contains("<TRANSLATE_THIS>four</TRANSLATE_THIS>"));
+    }
+
+    #[tokio::test]
+    async fn test_json_missing_ids() {
+        let backend = Arc::new(MockBackend::with_responses(vec![
+            r#"{"1": "一"}"#.to_string(),
+            "```json\n{\"1\": \"一\", \"2\": \"二\"}\n```".to_string(),
+        ]));
+        let mut translator = translator(backend.clone()).response_mode(ResponseMode::Json);
+        let chunks = vec!["one<T>two".to_string()];
+
+        let (text, _) = translate_chunk(
+            &mut translator,
+            &chunks,
+            &[],
+            0,
+            ContextWindow::Full,
+            "en",
+            "zh",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(text, "一<T>二");
+        let requests = backend.requests();
+        assert!(requests[0].contains("\"2\": \"two\""));
+        assert!(requests[1].contains("编号从1到2"));
+    }
+}
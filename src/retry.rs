@@ -0,0 +1,243 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use reqwest::header::HeaderMap;
+
+use crate::backend::BackendError;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_BASE_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// 服务端要求等待的时间超过这个值时不再重试
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// 后端请求的重试策略
+///
+/// 可重试的错误按指数退避加随机抖动等待后重试，服务端给出等待时间时以它为准
+#[derive(Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    retries: AtomicUsize,
+    rate_limited: AtomicUsize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ATTEMPTS)
+    }
+}
+
+impl RetryPolicy {
+    /// `max_attempts` 包含第一次请求，为 1 时不重试
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            retries: AtomicUsize::new(0),
+            rate_limited: AtomicUsize::new(0),
+        }
+    }
+
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub fn base_delay(mut self, value: Duration) -> Self {
+        self.base_delay = value;
+        self
+    }
+
+    /// 指数退避的最长等待时间
+    pub fn max_delay(mut self, value: Duration) -> Self {
+        self.max_delay = value;
+        self
+    }
+
+    /// 本次运行重试的次数
+    pub fn retries(&self) -> usize {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// 其中因为限流而重试的次数
+    pub fn rate_limited(&self) -> usize {
+        self.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// 第 `attempt` 次失败后的等待时间
+    fn delay(&self, attempt: u32, error: &BackendError) -> Duration {
+        if let Some(retry_after) = error.retry_after() {
+            // 加上抖动，避免并发的请求在同一时刻重试
+            return retry_after + jitter(self.base_delay);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        backoff / 2 + jitter(backoff / 2)
+    }
+
+    /// 执行请求，遇到可重试的错误时等待后重新执行，直到成功或达到最大次数
+    pub async fn run<T, F, Fut>(&self, mut request: F) -> Result<T, BackendError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BackendError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match request().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if !error.is_retryable()
+                || attempt >= self.max_attempts
+                || error
+                    .retry_after()
+                    .is_some_and(|after| after > MAX_RETRY_AFTER)
+            {
+                return Err(error);
+            }
+            let delay = self.delay(attempt, &error);
+            eprintln!(
+                "{}, retrying in {:.1}s ({}/{})",
+                error,
+                delay.as_secs_f64(),
+                attempt,
+                self.max_attempts - 1
+            );
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            if error.is_rate_limited() {
+                self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// `[0, max]` 之间的随机时长
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// 从响应头中读取服务端要求的等待时间
+///
+/// 依次查找 `retry-after-ms`、`retry-after`（秒数）和 OpenAI 的
+/// `x-ratelimit-reset-*`（仅在对应的 `x-ratelimit-remaining-*` 为 0 时）
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    if let Some(secs) = header("retry-after").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+    ["requests", "tokens"]
+        .iter()
+        .filter(|kind| header(&format!("x-ratelimit-remaining-{}", kind)) == Some("0"))
+        .filter_map(|kind| header(&format!("x-ratelimit-reset-{}", kind)))
+        .filter_map(parse_duration)
+        .max()
+}
+
+/// 解析 `1s`、`6m0s`、`20ms`、`1h2m3.5s` 这样的时长
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * unit;
+        rest = &rest[unit_len..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backend::TranslationBackend, mock::MockBackend};
+    use reqwest::{header::HeaderValue, StatusCode};
+
+    fn status(code: u16) -> BackendError {
+        BackendError::HttpError {
+            status: StatusCode::from_u16(code).unwrap(),
+            message: String::new(),
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(
+            "x-ratelimit-remaining-requests",
+            HeaderValue::from_static("0"),
+        );
+        headers.insert(
+            "x-ratelimit-reset-requests",
+            HeaderValue::from_static("1m30s"),
+        );
+        headers.insert(
+            "x-ratelimit-remaining-tokens",
+            HeaderValue::from_static("100"),
+        );
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("6m0s"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(90)));
+
+        headers.insert("retry-after", HeaderValue::from_static("20"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(20)));
+
+        assert_eq!(parse_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(
+            parse_duration("1h2m3.5s"),
+            Some(Duration::from_millis(3_723_500))
+        );
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let backend = MockBackend::with_results(vec![
+            Err(status(429)),
+            Err(BackendError::RequestError("connection reset".to_string())),
+            Ok("```\n译文\n```".to_string()),
+            Err(status(401)),
+        ]);
+        let retry = RetryPolicy::new(3).base_delay(Duration::ZERO);
+        let chat = || backend.chat(String::new(), String::new());
+
+        let response = retry.run(chat).await.unwrap();
+        assert_eq!(response.text, "```\n译文\n```");
+        assert_eq!((retry.retries(), retry.rate_limited()), (2, 1));
+
+        assert!(matches!(
+            retry.run(chat).await,
+            Err(BackendError::HttpError { status, .. }) if status == StatusCode::UNAUTHORIZED
+        ));
+        assert_eq!(backend.requests().len(), 4);
+    }
+}
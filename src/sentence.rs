@@ -0,0 +1,349 @@
+use std::ops::Range;
+
+use subparse::timetypes::TimeSpan;
+
+use crate::{
+    error::{Result, StcError},
+    markup,
+    tokens::is_cjk,
+};
+
+/// 两帧字幕间隔超过该毫秒数时不再视为同一句
+const MAX_GAP_MS: i64 = 1500;
+/// 一句话最多跨越的字幕数量
+const MAX_SENTENCE_CUES: usize = 4;
+/// 按比例计算的位置附近这么多字符以内有标点时，优先在标点之后断开
+const PUNCTUATION_TOLERANCE: usize = 2;
+
+const DELIMITER: &str = "<T>";
+
+/// 把跨越多帧字幕的句子合并为一个翻译单元
+///
+/// 翻译后按每帧字幕的时长和原文长度把译文分回原来的字幕，字幕数量和时间轴不变
+#[derive(Debug, Default)]
+pub struct Sentences {
+    /// 每个句子包含的字幕下标范围
+    groups: Vec<Range<usize>>,
+    /// 每帧字幕的原文
+    cues: Vec<String>,
+    /// 每帧字幕的时长（毫秒）
+    durations: Vec<i64>,
+}
+
+impl Sentences {
+    /// 按标点、间隔和对白破折号把相邻的字幕合并为句子
+    pub fn group(text_info: &[String], timespans: &[TimeSpan]) -> Self {
+        let mut groups: Vec<Range<usize>> = Vec::new();
+        for index in 0..text_info.len() {
+            match groups.last_mut() {
+                Some(group)
+                    if group.len() < MAX_SENTENCE_CUES
+                        && continues(
+                            &text_info[index - 1],
+                            &text_info[index],
+                            gap(timespans, index),
+                        ) =>
+                {
+                    group.end = index + 1
+                }
+                _ => groups.push(index..index + 1),
+            }
+        }
+        Self {
+            groups,
+            cues: text_info.to_vec(),
+            durations: timespans
+                .iter()
+                .map(|span| (span.end.msecs() - span.start.msecs()).max(0))
+                .collect(),
+        }
+    }
+
+    /// 句子数量
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// 每个句子包含的字幕下标范围
+    pub fn groups(&self) -> &[Range<usize>] {
+        &self.groups
+    }
+
+    /// 每个句子第一帧字幕的下标
+    pub fn first_cues(&self) -> impl Iterator<Item = usize> + '_ {
+        self.groups.iter().map(|group| group.start)
+    }
+
+    /// 每个句子的原文，跨越多帧的句子去掉原来的换行
+    pub fn text_info(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .map(|group| match group.len() {
+                1 => self.cues[group.start].clone(),
+                _ => self.cues[group.clone()]
+                    .iter()
+                    .map(|cue| cue.replace("<nl>", " "))
+                    .reduce(|sentence, cue| join(&sentence, &cue))
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// `sentences` 范围内的句子对应的字幕原文，用 `<T>` 连接
+    pub fn source(&self, sentences: Range<usize>) -> String {
+        let cues = self.cue_range(sentences);
+        self.cues[cues].join(DELIMITER)
+    }
+
+    /// 把从第 `first` 个句子开始、用 `<T>` 连接的译文分回每帧字幕
+    pub fn expand(&self, first: usize, translated_text: &str) -> Result<String> {
+        let translated: Vec<&str> = translated_text.split(DELIMITER).collect();
+        if first + translated.len() > self.groups.len() {
+            return Err(StcError::AlignmentError(format!(
+                "Sentences {} to {} out of range, only {} sentences",
+                first + 1,
+                first + translated.len(),
+                self.groups.len()
+            )));
+        }
+        let pieces: Vec<String> = self.groups[first..first + translated.len()]
+            .iter()
+            .zip(translated)
+            .flat_map(|(group, text)| self.redistribute(group.clone(), text))
+            .collect();
+        Ok(pieces.join(DELIMITER))
+    }
+
+    fn cue_range(&self, sentences: Range<usize>) -> Range<usize> {
+        match (
+            self.groups.get(sentences.start),
+            self.groups.get(sentences.end.saturating_sub(1)),
+        ) {
+            (Some(first), Some(last)) if !sentences.is_empty() => first.start..last.end,
+            _ => 0..0,
+        }
+    }
+
+    /// 按时长和原文长度各占一半的权重把一句译文分给 `cues` 中的字幕
+    fn redistribute(&self, cues: Range<usize>, text: &str) -> Vec<String> {
+        if cues.len() == 1 {
+            return vec![text.to_string()];
+        }
+        let lengths: Vec<f64> = self.cues[cues.clone()]
+            .iter()
+            .map(|cue| cue.replace("<nl>", "").chars().count() as f64)
+            .collect();
+        let durations: Vec<f64> = self.durations[cues].iter().map(|&d| d as f64).collect();
+        let weights: Vec<f64> = lengths
+            .iter()
+            .zip(&durations)
+            .map(|(length, duration)| {
+                share(*length, &lengths) / 2.0 + share(*duration, &durations) / 2.0
+            })
+            .collect();
+        split_proportionally(text, &weights)
+    }
+}
+
+/// `value` 占 `values` 总和的比例，总和为 0 时平均分配
+fn share(value: f64, values: &[f64]) -> f64 {
+    let total: f64 = values.iter().sum();
+    if total > 0.0 {
+        value / total
+    } else {
+        1.0 / values.len() as f64
+    }
+}
+
+/// 第 `index` 帧字幕与上一帧之间的间隔（毫秒）
+fn gap(timespans: &[TimeSpan], index: usize) -> i64 {
+    match (timespans.get(index - 1), timespans.get(index)) {
+        (Some(previous), Some(current)) => current.start.msecs() - previous.end.msecs(),
+        _ => i64::MAX,
+    }
+}
+
+/// 上一帧没有说完、下一帧紧接着开始且不是新的对白时视为同一句，带标签的字幕不合并
+fn continues(previous: &str, next: &str, gap: i64) -> bool {
+    // 按比例拆分译文会把标签的占位符分到错误的字幕
+    if markup::has_placeholders(previous) || markup::has_placeholders(next) {
+        return false;
+    }
+    let previous = previous
+        .trim_end()
+        .trim_end_matches(['"', '\'', '”', '’', ')', '）', '」', '』']);
+    let ended = previous.ends_with([
+        '.', '!', '?', '…', '♪', ':', ';', '。', '！', '？', '；', '：',
+    ]);
+    let dialogue = next.trim_start().starts_with(['-', '–', '—']);
+    !previous.is_empty() && !ended && !dialogue && gap <= MAX_GAP_MS
+}
+
+/// 连接同一句的两部分，中日韩文字之间不加空格
+pub(crate) fn join(left: &str, right: &str) -> String {
+    let (left, right) = (left.trim_end(), right.trim_start());
+    match (left.chars().last(), right.chars().next()) {
+        (Some(l), Some(r)) if is_cjk(l) && is_cjk(r) => format!("{}{}", left, right),
+        _ => format!("{} {}", left, right),
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    matches!(
+        c,
+        ',' | '.'
+            | '!'
+            | '?'
+            | ';'
+            | ':'
+            | '，'
+            | '。'
+            | '、'
+            | '！'
+            | '？'
+            | '；'
+            | '：'
+            | '…'
+            | '—'
+    )
+}
+
+/// 能否在 `position` 处断开：空白或标点之后，或者中日韩文字之间
+fn can_break(chars: &[char], position: usize) -> bool {
+    let (previous, next) = (chars[position - 1], chars[position]);
+    previous.is_whitespace() || is_punctuation(previous) || is_cjk(previous) || is_cjk(next)
+}
+
+fn nearest(positions: impl Iterator<Item = usize>, target: usize) -> Option<usize> {
+    positions.min_by_key(|position| position.abs_diff(target))
+}
+
+/// 按权重把文本切成 `weights.len()` 段，尽量在空白或标点之后断开
+fn split_proportionally(text: &str, weights: &[f64]) -> Vec<String> {
+    let chars: Vec<char> = text.trim().chars().collect();
+    let count = weights.len();
+    let total = chars.len();
+    let mut cuts = Vec::with_capacity(count + 1);
+    cuts.push(0);
+    let mut cumulative = 0.0;
+    for (k, weight) in weights[..count - 1].iter().enumerate() {
+        cumulative += weight;
+        let last = *cuts.last().unwrap_or(&0);
+        // 每段至少留一个字符
+        let lowest = (last + 1).min(total);
+        let highest = total.saturating_sub(count - 1 - k).max(lowest);
+        let target = ((cumulative * total as f64).round() as usize).clamp(lowest, highest);
+        let candidates = (lowest..=highest).filter(|&p| p > 0 && p < total);
+        let cut = nearest(
+            candidates
+                .clone()
+                .filter(|&p| is_punctuation(chars[p - 1]))
+                .filter(|p| p.abs_diff(target) <= PUNCTUATION_TOLERANCE),
+            target,
+        )
+        .or_else(|| nearest(candidates.filter(|&p| can_break(&chars, p)), target))
+        .unwrap_or(target);
+        cuts.push(cut);
+    }
+    cuts.push(total);
+    cuts.windows(2)
+        .map(|range| {
+            chars[range[0]..range[1]]
+                .iter()
+                .collect::<String>()
+                .trim()
+                .to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use subparse::timetypes::TimePoint;
+
+    fn timespan(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end))
+    }
+
+    #[test]
+    fn test_group() {
+        let text_info: Vec<String> = [
+            "But you have to understand, we Brits",
+            "are fighting a desperate battle.",
+            "[[1]]- Who are you?[[2]]",
+            "- Nobody.",
+            "[[1]]I waited[[2]]",
+            "for years.",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let timespans = vec![
+            timespan(0, 2000),
+            timespan(2100, 4000),
+            timespan(5000, 6000),
+            timespan(6100, 7000),
+            timespan(7100, 8000),
+            timespan(8100, 9000),
+        ];
+
+        let sentences = Sentences::group(&text_info, &timespans);
+
+        // 带标签的字幕不合并
+        assert_eq!(sentences.len(), 5);
+        assert_eq!(
+            sentences.text_info()[..2],
+            [
+                "But you have to understand, we Brits are fighting a desperate battle.",
+                "[[1]]- Who are you?[[2]]",
+            ]
+        );
+        assert_eq!(
+            sentences.source(0..2),
+            "But you have to understand, we Brits<T>are fighting a desperate battle.<T>[[1]]- Who are you?[[2]]"
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let text_info = vec![
+            "But you have to understand, we Brits".to_string(),
+            "are fighting a desperate battle.".to_string(),
+            "Go.".to_string(),
+        ];
+        let timespans = vec![
+            timespan(0, 2000),
+            timespan(2000, 4000),
+            timespan(4000, 5000),
+        ];
+        let sentences = Sentences::group(&text_info, &timespans);
+
+        let expanded = sentences
+            .expand(0, "但你要明白，我们英国人正在打一场殊死之战。<T>走。")
+            .unwrap();
+
+        assert_eq!(
+            expanded,
+            "但你要明白，我们英国人<T>正在打一场殊死之战。<T>走。"
+        );
+        assert!(sentences.expand(1, "a<T>b").is_err());
+    }
+
+    #[test]
+    fn test_split_proportionally() {
+        assert_eq!(
+            split_proportionally("一二三四", &[0.5, 0.5]),
+            ["一二", "三四"]
+        );
+        assert_eq!(split_proportionally("一", &[0.5, 0.5]), ["一", ""]);
+        assert_eq!(
+            split_proportionally("one two three four", &[0.5, 0.5]),
+            ["one two", "three four"]
+        );
+    }
+}
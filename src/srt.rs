@@ -0,0 +1,97 @@
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+/// SRT 时间格式 `HH:MM:SS,mmm`，负数时间按 0 处理
+pub fn format_timestamp(t: TimePoint) -> String {
+    let t = if t.msecs() < 0 {
+        TimePoint::from_msecs(0)
+    } else {
+        t
+    };
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        t.hours(),
+        t.mins_comp(),
+        t.secs_comp(),
+        t.msecs_comp()
+    )
+}
+
+/// SRT 时间轴 `00:01:34,095 --> 00:01:36,180`
+pub fn format_timespan(timespan: TimeSpan) -> String {
+    format!(
+        "{} --> {}",
+        format_timestamp(timespan.start),
+        format_timestamp(timespan.end)
+    )
+}
+
+/// 统一换行符为 `\n`，去掉行尾空白和空行，避免空行提前结束一条字幕
+pub fn normalize_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 把字幕序列化为 SRT，从 `first_number` 开始连续编号
+///
+/// 每条字幕以空行结束，多段结果可以直接拼接
+pub fn serialize<'a>(
+    cues: impl IntoIterator<Item = (TimeSpan, &'a str)>,
+    first_number: usize,
+) -> String {
+    let mut content = String::new();
+    for (index, (timespan, text)) in cues.into_iter().enumerate() {
+        content.push_str(&format!(
+            "{}\n{}\n{}\n\n",
+            first_number + index,
+            format_timespan(timespan),
+            normalize_text(text)
+        ));
+    }
+    content
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn timespan(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end))
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(
+            format_timestamp(TimePoint::from_msecs(94095)),
+            "00:01:34,095"
+        );
+        assert_eq!(
+            format_timestamp(TimePoint::from_msecs(36_000_000 + 5)),
+            "10:00:00,005"
+        );
+        assert_eq!(format_timestamp(TimePoint::from_msecs(-20)), "00:00:00,000");
+    }
+
+    #[test]
+    fn test_serialize() {
+        let cues = [
+            (
+                timespan(94095, 96180),
+                "Where in hell is\r\nyour so-called magician?  ",
+            ),
+            (timespan(96260, 98300), "He swore an oath.\n\n"),
+        ];
+
+        let content = serialize(cues, 7);
+
+        assert_eq!(
+            content,
+            "7\n00:01:34,095 --> 00:01:36,180\nWhere in hell is\nyour so-called magician?\n\n\
+             8\n00:01:36,260 --> 00:01:38,300\nHe swore an oath.\n\n"
+        );
+    }
+}
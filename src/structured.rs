@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// 模型返回译文的方式
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseMode {
+    /// 在最后一个代码块中用 `<T>` 分隔每一帧字幕
+    #[default]
+    Delimited,
+    /// 返回以字幕编号为 key 的 JSON 对象
+    Json,
+}
+
+impl std::fmt::Display for ResponseMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ResponseMode::Delimited => "delimited",
+            ResponseMode::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 把 `<T>` 分隔的分块转换为从 1 开始编号的 JSON 对象
+pub fn to_json(chunk: &str) -> String {
+    let lines: Vec<String> = chunk
+        .split("<T>")
+        .enumerate()
+        .map(|(index, text)| format!("  \"{}\": {}", index + 1, Value::from(text)))
+        .collect();
+    format!("{{\n{}\n}}", lines.join(",\n"))
+}
+
+/// 要求模型返回 `count` 个编号的 JSON schema
+pub fn schema(count: usize) -> Value {
+    let ids: Vec<String> = (1..=count).map(|id| id.to_string()).collect();
+    let properties: Map<String, Value> = ids
+        .iter()
+        .map(|id| (id.clone(), json!({ "type": "string" })))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": ids,
+        "additionalProperties": false,
+    })
+}
+
+/// 从回复中找出 JSON 对象，允许前后有说明文字或代码块标记
+pub fn extract_object(text: &str) -> Option<Map<String, Value>> {
+    if let Ok(Value::Object(object)) = serde_json::from_str(text.trim()) {
+        return Some(object);
+    }
+    // 从最后一个代码块开始向前尝试，最后尝试第一个 `{` 到最后一个 `}` 之间的内容
+    let blocks = text.rsplit("```").map(|block| {
+        block
+            .trim()
+            .trim_start_matches("json")
+            .trim_start_matches("JSON")
+    });
+    let braces = text
+        .find('{')
+        .zip(text.rfind('}'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &text[start..=end]);
+    blocks
+        .chain(braces)
+        .find_map(|candidate| match serde_json::from_str(candidate.trim()) {
+            Ok(Value::Object(object)) => Some(object),
+            _ => None,
+        })
+}
+
+/// 按编号 1 到 `count` 取出译文，缺少编号或无法解析时返回原因
+///
+/// 多出的编号被忽略，译文中的 `<T>` 替换为空格以免破坏分段
+pub fn parse(text: &str, count: usize) -> Result<Vec<String>, String> {
+    let object = extract_object(text).ok_or("no JSON object found")?;
+    let mut missing = Vec::new();
+    let segments: Vec<String> = (1..=count)
+        .map(|id| match object.get(&id.to_string()) {
+            Some(Value::String(text)) => text.trim().replace("<T>", " "),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+            _ => {
+                missing.push(id.to_string());
+                String::new()
+            }
+        })
+        .collect();
+    match missing.is_empty() {
+        true => Ok(segments),
+        false => Err(format!("missing IDs {}", missing.join(", "))),
+    }
+}
+
+/// 取出 JSON 对象中的所有译文并用空格连接，无法解析时返回 `None`
+pub fn join_values(text: &str) -> Option<String> {
+    let object = extract_object(text)?;
+    let values: Vec<&str> = object.values().filter_map(Value::as_str).collect();
+    Some(values.join(" ").replace("<T>", " "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let json = to_json("Where in hell is<nl>your \"magician\"?<T>He swore an oath.");
+
+        assert_eq!(
+            json,
+            "{\n  \"1\": \"Where in hell is<nl>your \\\"magician\\\"?\",\n  \"2\": \"He swore an oath.\"\n}"
+        );
+        assert_eq!(schema(2)["required"], json!(["1", "2"]));
+    }
+
+    #[test]
+    fn test_parse() {
+        let fenced =
+            "好的，译文如下：\n```json\n{\"1\": \"你的魔法师在哪？\", \"2\": \"他发过誓。\"}\n```";
+        let wrapped = "Translation: {\"2\": \"他发过誓。\", \"1\": \"你的魔法师在哪？\", \"3\": \"多余\"} done";
+
+        let expected = vec!["你的魔法师在哪？".to_string(), "他发过誓。".to_string()];
+        assert_eq!(parse(fenced, 2), Ok(expected.clone()));
+        assert_eq!(parse(wrapped, 2), Ok(expected));
+        assert_eq!(
+            parse("{\"1\": \"只有一段\"}", 3),
+            Err("missing IDs 2, 3".to_string())
+        );
+        assert!(parse("没有 JSON", 1).is_err());
+    }
+}
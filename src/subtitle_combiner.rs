@@ -1,33 +1,98 @@
-use thiserror::Error;
+use serde::Deserialize;
+use subparse::timetypes::TimeSpan;
 
-#[derive(Error, Debug)]
-pub enum SubtitleError {
-    #[error("Invalid input: {0}")]
-    InvalidInput(String),
+use crate::{
+    error::{Result, StcError},
+    markup,
+    wrap::{wrap, WrapSettings},
+};
+
+/// 双语字幕的排列方式
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// 只保留译文
+    TranslationOnly,
+    /// 译文在上，原文在下
+    #[default]
+    TranslationAbove,
+    /// 原文在上，译文在下
+    OriginalAbove,
+    /// 译文和原文分别写入两个单语文件
+    Separate,
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Layout::TranslationOnly => "translation-only",
+            Layout::TranslationAbove => "translation-above",
+            Layout::OriginalAbove => "original-above",
+            Layout::Separate => "separate",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Layout {
+    /// 按布局排列一条字幕的文本，没有译文时只保留原文
+    ///
+    /// `Separate` 在这里只返回译文，原文由调用方单独输出
+    pub fn arrange(&self, cue: &CombinedCue) -> String {
+        match (self, &cue.translated) {
+            (_, None) => cue.original.clone(),
+            (Layout::TranslationOnly | Layout::Separate, Some(translated)) => translated.clone(),
+            (Layout::TranslationAbove, Some(translated)) => {
+                format!("{}\n{}", translated, cue.original)
+            }
+            (Layout::OriginalAbove, Some(translated)) => {
+                format!("{}\n{}", cue.original, translated)
+            }
+        }
+    }
+}
+
+/// 合并后的一条字幕，多行文本用 `\n` 分隔
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedCue {
+    /// 译文，分段数量对不上时为 `None`
+    pub translated: Option<String>,
+    pub original: String,
 }
 
 #[derive(Debug)]
 pub struct CombineInput {
     pub combined_text: String,
     pub translated_text: String,
-    pub time_info: Vec<String>,
-    pub number_info: Vec<String>,
+    pub timespans: Vec<TimeSpan>,
+    /// 每条字幕被占位符替换的格式标签
+    pub markup: Vec<Vec<String>>,
+    /// 每条字幕在原字幕条目中的下标，日志中的行号按原文件计算
+    pub indices: Vec<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SubtitleCombiner {
-    srt_content: String,
+    cues: Vec<CombinedCue>,
     current_index: usize,
+    wrap: Option<WrapSettings>,
 }
 
 impl SubtitleCombiner {
     pub fn new() -> Self {
         Self {
-            srt_content: String::new(),
+            cues: Vec::new(),
             current_index: 0,
+            wrap: None,
         }
     }
 
+    /// 译文的换行规则，`None` 时保留模型给出的换行
+    pub fn wrap(mut self, value: Option<WrapSettings>) -> Self {
+        self.wrap = value;
+        self
+    }
+
     pub fn current_index(mut self, value: usize) -> Self {
         self.current_index = value;
         self
@@ -38,66 +103,173 @@ impl SubtitleCombiner {
         self.current_index
     }
 
-    /// 获取合并后的字幕内容
-    pub fn get_content(&self) -> &str {
-        &self.srt_content
+    /// 获取最近一次合并得到的字幕
+    pub fn get_cues(&self) -> &[CombinedCue] {
+        &self.cues
     }
 
     /// 合并字幕内容
     ///
     /// combined_text: 原文
     /// translated_text: 翻译后的文本
-    pub fn combine(&mut self, input: CombineInput) -> Result<(), SubtitleError> {
+    pub fn combine(&mut self, input: CombineInput) -> Result<()> {
         // eprintln!("input\n{:?}", input);
         let combined_text: Vec<&str> = input.combined_text.split("<T>").collect();
         let translated_text: Vec<&str> = input.translated_text.split("<T>").collect();
 
+        // 原文件中的行号，没有下标时按提取后的顺序计算
+        let line = |pos: usize| input.indices.get(pos).copied().unwrap_or(pos) + 1;
         if combined_text.len() != translated_text.len() {
+            let last = self.current_index + combined_text.len() - 1;
             eprintln!(
                 "Line {} to {} need manual translation",
-                input.number_info[self.current_index],
-                input.number_info[self.current_index + combined_text.len() - 1]
+                line(self.current_index),
+                line(last)
             );
         }
-        let mut combined_lines = Vec::with_capacity(combined_text.len() * 5);
-
-        for (index, (translated_line, combined_line)) in
-            translated_text.iter().zip(combined_text.iter()).enumerate()
-        {
-            // eprintln!(
-            //     "index: {}, translated_line: {}, combined_line: {}",
-            //     index, translated_line, combined_line
-            // );
+        self.cues.clear();
+
+        for (index, combined_line) in combined_text.iter().enumerate() {
             let current_pos = self.current_index + index;
 
-            if current_pos >= input.number_info.len() || current_pos >= input.time_info.len() {
-                return Err(SubtitleError::InvalidInput(
-                    "Index out of bounds for number_info or time_info".to_string(),
+            if input.timespans.get(current_pos).is_none() {
+                return Err(StcError::AlignmentError(
+                    "Index out of bounds for timespans".to_string(),
                 ));
-            }
+            };
 
-            let mut entry = vec![
-                input.number_info[current_pos].clone(),
-                input.time_info[current_pos].clone(),
-            ];
+            let tags = input.markup.get(current_pos).map_or(&[][..], Vec::as_slice);
             // 如果文本行和结果行数量不匹配，则不合并翻译后的文本
-            if combined_text.len() != translated_text.len() {
-                entry.push(combined_line.to_string());
-            } else {
-                entry.extend([
-                    translated_line.trim().to_string(),
-                    combined_line.to_string(),
-                ]);
-            }
-            entry.push(String::new());
-            combined_lines.extend(entry);
+            let translated = (combined_text.len() == translated_text.len()).then(|| {
+                let translated = translated_text[index].trim().replace("<nl>", "\n");
+                let translated = match &self.wrap {
+                    Some(settings) => wrap(&translated, settings),
+                    None => translated,
+                };
+                let (translated, lost) = markup::restore(&translated, combined_line, tags);
+                if !lost.is_empty() {
+                    eprintln!(
+                        "Line {} lost formatting tags, check the translation manually",
+                        line(current_pos)
+                    );
+                }
+                translated
+            });
+            let cue = CombinedCue {
+                translated,
+                original: markup::restore(combined_line, combined_line, tags)
+                    .0
+                    .replace("<nl>", "\n"),
+            };
+            self.cues.push(cue);
         }
-        // eprintln!("combined_lines:\n {:?}", combined_lines);
-        // add \n in the end
-        self.srt_content = combined_lines.join("\n").replace("<nl>", "\n");
-        self.srt_content.push('\n');
-        // eprintln!("srt_content:\n {:?}", self.srt_content);
-        self.current_index += translated_text.len();
+        self.current_index += combined_text.len();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use subparse::timetypes::TimePoint;
+
+    fn input(translated_text: &str) -> CombineInput {
+        CombineInput {
+            combined_text: "Where in hell is<nl>your so-called magician?<T>He swore an oath."
+                .to_string(),
+            translated_text: translated_text.to_string(),
+            timespans: vec![
+                TimeSpan::new(TimePoint::from_msecs(94095), TimePoint::from_msecs(96180)),
+                TimeSpan::new(TimePoint::from_msecs(96260), TimePoint::from_msecs(98300)),
+            ],
+            markup: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_layouts() {
+        let cases = [
+            (Layout::TranslationOnly, "你的魔法师在哪？"),
+            (
+                Layout::TranslationAbove,
+                "你的魔法师在哪？\nWhere in hell is\nyour so-called magician?",
+            ),
+            (
+                Layout::OriginalAbove,
+                "Where in hell is\nyour so-called magician?\n你的魔法师在哪？",
+            ),
+        ];
+        let mut combiner = SubtitleCombiner::new();
+
+        combiner
+            .combine(input("你的魔法师在哪？<T>他发过誓。"))
+            .unwrap();
+
+        assert_eq!(combiner.get_cues().len(), 2);
+        for (layout, expected) in cases {
+            assert_eq!(
+                layout.arrange(&combiner.get_cues()[0]),
+                expected,
+                "{}",
+                layout
+            );
+        }
+    }
+
+    #[test]
+    fn test_mismatch_keeps_original() {
+        let mut combiner = SubtitleCombiner::new();
+
+        combiner.combine(input("只有一段")).unwrap();
+
+        assert_eq!(combiner.get_current_index(), 2);
+        assert!(combiner
+            .get_cues()
+            .iter()
+            .all(|cue| cue.translated.is_none()));
+        assert_eq!(combiner.get_cues()[1].original, "He swore an oath.");
+    }
+
+    #[test]
+    fn test_restore_markup() {
+        let mut combiner = SubtitleCombiner::new();
+        let input = CombineInput {
+            combined_text: "[[1]]Magic does exist.[[2]]<T>[[1]]London.".to_string(),
+            translated_text: "[[1]]魔法确实存在。[[2]]<T>伦敦。".to_string(),
+            timespans: input("").timespans,
+            markup: vec![
+                vec!["<i>".to_string(), "</i>".to_string()],
+                vec!["{\\an8}".to_string()],
+            ],
+            indices: vec![0, 1],
+        };
+
+        combiner.combine(input).unwrap();
+
+        let cues = combiner.get_cues();
+        assert_eq!(cues[0].translated.as_deref(), Some("<i>魔法确实存在。</i>"));
+        assert_eq!(cues[0].original, "<i>Magic does exist.</i>");
+        // 丢失的占位符补回到原来的位置
+        assert_eq!(cues[1].translated.as_deref(), Some("{\\an8}伦敦。"));
+    }
+
+    #[test]
+    fn test_wrap_translation() {
+        let mut combiner = SubtitleCombiner::new().wrap(Some(WrapSettings {
+            max_width: 16,
+            max_lines: 2,
+        }));
+
+        combiner
+            .combine(input("你那个所谓的<nl>魔法师究竟在哪里？<T>他发过誓。"))
+            .unwrap();
+
+        let cues = combiner.get_cues();
+        assert_eq!(
+            cues[0].translated.as_deref(),
+            Some("你那个所谓的魔\n法师究竟在哪里？")
+        );
+        assert_eq!(cues[1].translated.as_deref(), Some("他发过誓。"));
+    }
+}
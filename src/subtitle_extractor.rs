@@ -1,44 +1,69 @@
-use subparse::SubtitleEntry;
+use subparse::{timetypes::TimeSpan, SubtitleEntry};
+
+use crate::{error::Result, markup};
 
 /// 字幕提取器，用于存储和处理字幕信息
-#[derive(Debug)]
+///
+/// 只提取有文本的字幕，没有文本的条目（图片字幕、空注释等）不参与翻译
+#[derive(Debug, Default)]
 pub struct SubtitleExtractor {
-    pub time_info: Vec<String>,
+    pub timespans: Vec<TimeSpan>,
     pub text_info: Vec<String>,
-    pub number_info: Vec<String>,
+    /// 每条提取的字幕在原字幕条目中的下标
+    pub indices: Vec<usize>,
+    /// 每条字幕中被占位符替换的格式标签
+    pub markup: Vec<Vec<String>>,
 }
 
 impl SubtitleExtractor {
     pub fn new() -> Self {
         Self {
-            time_info: Vec::new(),
+            timespans: Vec::new(),
             text_info: Vec::new(),
-            number_info: Vec::new(),
+            indices: Vec::new(),
+            markup: Vec::new(),
         }
     }
-    /// 格式化时间信息
-    fn format_time_info(entry: &SubtitleEntry) -> String {
-        format!("{} --> {}", entry.timespan.start, entry.timespan.end)
+    /// 处理文本信息，把换行符替换为 `<nl>`，`ass` 为 true 时 `\N`、`\n` 换行也替换为 `<nl>`，
+    /// 没有文本或只有空白时返回 `None`
+    fn process_text_info(entry: &SubtitleEntry, ass: bool) -> Option<String> {
+        entry
+            .line
+            .as_ref()
+            .filter(|text| !text.trim().is_empty())
+            .map(|text| {
+                let text = if ass {
+                    text.replace("\\N", "\n").replace("\\n", "\n")
+                } else {
+                    text.clone()
+                };
+                text.replace("\n", "<nl>")
+            })
+    }
+
+    /// 从字幕条目提取信息，格式标签替换为占位符
+    pub fn extractor(entries: &[SubtitleEntry]) -> Result<Self> {
+        Self::extract(entries, false)
     }
 
-    /// 处理文本信息，替换换行符
-    fn process_text_info(entry: &SubtitleEntry) -> Option<String> {
-        entry.line.clone().map(|text| text.replace("\n", "<nl>"))
+    /// 从 SSA/ASS 字幕条目提取信息，`\N` 和 `\n` 按换行处理
+    pub fn ass_extractor(entries: &[SubtitleEntry]) -> Result<Self> {
+        Self::extract(entries, true)
     }
 
-    /// 从字幕条目提取信息
-    pub fn extractor(entries: &Vec<SubtitleEntry>) -> Result<Self, String> {
+    fn extract(entries: &[SubtitleEntry], ass: bool) -> Result<Self> {
         let mut subtitle_extractor = Self::new();
 
         for (index, entry) in entries.iter().enumerate() {
-            let time_info = Self::format_time_info(entry);
+            let Some(text_info) = Self::process_text_info(entry, ass) else {
+                continue;
+            };
+            let (text_info, tags) = markup::protect(&text_info);
 
-            let text_info = Self::process_text_info(entry)
-                .ok_or_else(|| format!("No line found at index {}", index))?;
-
-            subtitle_extractor.time_info.push(time_info);
+            subtitle_extractor.timespans.push(entry.timespan);
             subtitle_extractor.text_info.push(text_info);
-            subtitle_extractor.number_info.push((index + 1).to_string());
+            subtitle_extractor.indices.push(index);
+            subtitle_extractor.markup.push(tags);
         }
 
         Ok(subtitle_extractor)
@@ -60,33 +85,71 @@ mod test {
 
         let extractor = result.unwrap();
 
-        assert_eq!(extractor.number_info.len(), 60);
-        assert_eq!(extractor.time_info.len(), 60);
+        assert_eq!(extractor.timespans.len(), 60);
         assert_eq!(extractor.text_info.len(), 60);
     }
 
     #[test]
-    fn test_format_time_info() {
+    fn test_process_text_info() {
         let path = std::path::PathBuf::from("test.srt");
         let subtitle_entries = parse::parse_file(&path).expect("Failed to parse file");
         let entry = &subtitle_entries[0];
 
-        let time_info = SubtitleExtractor::format_time_info(entry);
+        let text_info = SubtitleExtractor::process_text_info(entry, false);
 
-        assert_eq!(time_info, "0:01:34.095 --> 0:01:36.180");
+        assert_eq!(
+            text_info,
+            Some("Where in hell is<nl>your so-called magician?".to_string())
+        );
     }
 
     #[test]
-    fn test_process_text_info() {
-        let path = std::path::PathBuf::from("test.srt");
+    fn test_extractor_ass() {
+        let path = std::path::PathBuf::from("test.ass");
         let subtitle_entries = parse::parse_file(&path).expect("Failed to parse file");
-        let entry = &subtitle_entries[0];
+        let entry = SubtitleEntry {
+            timespan: subtitle_entries[1].timespan,
+            line: Some("He swore\\nan oath.".to_string()),
+        };
 
-        let text_info = SubtitleExtractor::process_text_info(entry);
+        let extractor = SubtitleExtractor::ass_extractor(&subtitle_entries).unwrap();
 
         assert_eq!(
-            text_info,
-            Some("Where in hell is<nl>your so-called magician?".to_string())
+            extractor.text_info[0],
+            "Where in hell is<nl>your so-called magician?"
+        );
+        assert_eq!(extractor.text_info[2], "[[1]]Oxford University");
+        assert_eq!(
+            SubtitleExtractor::process_text_info(&entry, true),
+            Some("He swore<nl>an oath.".to_string())
         );
     }
+
+    #[test]
+    fn test_extractor_keeps_backslash_outside_ass() {
+        let entries = vec![SubtitleEntry {
+            timespan: TimeSpan::new(
+                subparse::timetypes::TimePoint::from_msecs(0),
+                subparse::timetypes::TimePoint::from_msecs(1000),
+            ),
+            line: Some("C:\\New\nfolder".to_string()),
+        }];
+
+        let extractor = SubtitleExtractor::extractor(&entries).unwrap();
+
+        assert_eq!(extractor.text_info[0], "C:\\New<nl>folder");
+    }
+
+    #[test]
+    fn test_skip_entries_without_text() {
+        let mut subtitle_entries =
+            parse::parse_file(std::path::Path::new("test.srt")).expect("Failed to parse file");
+        subtitle_entries[1].line = None;
+        subtitle_entries[2].line = Some("  ".to_string());
+
+        let extractor = SubtitleExtractor::extractor(&subtitle_entries).unwrap();
+
+        assert_eq!(extractor.text_info.len(), 58);
+        assert_eq!(&extractor.indices[..3], &[0, 3, 4]);
+    }
 }
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use tera::Tera;
+
+use crate::error::Result;
+
+/// 编译进程序的默认模板
+pub const DEFAULT_TEMPLATES: [(&str, &str); 4] = [
+    ("prompt.txt", include_str!("templates/prompt.txt")),
+    ("json_prompt.txt", include_str!("templates/json_prompt.txt")),
+    (
+        "user_message.txt",
+        include_str!("templates/user_message.txt"),
+    ),
+    (
+        "repair_message.txt",
+        include_str!("templates/repair_message.txt"),
+    ),
+];
+
+/// 加载模板
+///
+/// 先加载内置的默认模板，再用 `override_dir` 中的同名文件逐个覆盖，
+/// 目录中没有的文件继续使用默认模板
+///
+/// # Errors
+/// 模板文件无法读取或存在语法错误时返回错误
+pub fn load_templates(override_dir: Option<&Path>) -> Result<Tera> {
+    let mut tera = Tera::default();
+    tera.add_raw_templates(DEFAULT_TEMPLATES)?;
+
+    if let Some(dir) = override_dir {
+        for (name, _) in DEFAULT_TEMPLATES {
+            let path = dir.join(name);
+            if path.is_file() {
+                tera.add_template_file(&path, Some(name))?;
+            }
+        }
+    }
+    Ok(tera)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_default_templates() {
+        let tera = load_templates(None).unwrap();
+
+        let mut context = tera::Context::new();
+        context.insert("source_language", "en");
+        context.insert("target_language", "ja");
+        let prompt = tera.render("prompt.txt", &context).unwrap();
+
+        assert!(prompt.contains("en和ja字幕翻译专家"));
+    }
+
+    #[test]
+    fn test_override_single_template() {
+        let dir = crate::mock::temp_dir("templates");
+        std::fs::write(dir.join("prompt.txt"), "Translate {{source_language}}").unwrap();
+
+        let tera = load_templates(Some(&dir)).unwrap();
+
+        let mut context = tera::Context::new();
+        context.insert("source_language", "en");
+        assert_eq!(tera.render("prompt.txt", &context).unwrap(), "Translate en");
+        let names: Vec<_> = tera.get_template_names().collect();
+        assert!(names.contains(&"user_message.txt"));
+    }
+
+    #[test]
+    fn test_invalid_override() {
+        let dir = crate::mock::temp_dir("templates_invalid");
+        std::fs::write(dir.join("user_message.txt"), "{{ unclosed").unwrap();
+
+        assert!(load_templates(Some(&dir)).is_err());
+    }
+}
@@ -1,6 +1,41 @@
-use crate::GROUP_SIZE;
+use crate::{
+    error::{Result, StcError},
+    tokens::estimate_tokens,
+};
+
+/// 每个分块的原文 token 预算
+pub const DEFAULT_CHUNK_TOKENS: usize = 300;
+pub const DEFAULT_MIN_CUES: usize = 1;
+pub const DEFAULT_MAX_CUES: usize = 30;
+/// 字幕之间的静默超过该毫秒数时视为换场
+pub const DEFAULT_SCENE_GAP_MS: u64 = 3000;
+
+/// 分块的大小限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSettings {
+    /// 每个分块中原文的估算 token 上限
+    pub max_tokens: usize,
+    /// 每个分块至少包含的字幕数量，优先于 token 上限
+    pub min_cues: usize,
+    /// 每个分块最多包含的字幕数量
+    pub max_cues: usize,
+    /// 达到一半容量的分块遇到这么长的静默时提前结束
+    pub scene_gap_ms: u64,
+}
+
+impl Default for ChunkSettings {
+    fn default() -> Self {
+        Self {
+            max_tokens: DEFAULT_CHUNK_TOKENS,
+            min_cues: DEFAULT_MIN_CUES,
+            max_cues: DEFAULT_MAX_CUES,
+            scene_gap_ms: DEFAULT_SCENE_GAP_MS,
+        }
+    }
+}
+
 /// 文本分割器，用于将文本按组分割
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TextSplitter {
     pub split_result: Vec<String>,
 }
@@ -15,32 +50,83 @@ impl TextSplitter {
             split_result: Vec::new(),
         }
     }
-    /// 将文本按组大小分割
+
+    /// 使用默认的分块设置分割文本
+    pub fn split_text(text_info: &[String]) -> Result<Self> {
+        Self::split_with(text_info, &ChunkSettings::default())
+    }
+
+    /// 按 token 预算把字幕依次装入分块，不考虑时间轴
+    pub fn split_with(text_info: &[String], settings: &ChunkSettings) -> Result<Self> {
+        Self::split_timed(text_info, &[], settings)
+    }
+
+    /// 按 token 预算把字幕依次装入分块，尽量在长时间的静默处断开
+    ///
+    /// 当前分块不足 `min_cues` 条时总是加入下一条字幕；否则在加入后会超出
+    /// `max_tokens` 或达到 `max_cues` 时结束分块，并退回到分块后半部分中
+    /// 静默最长的位置断开。已用掉一半容量的分块遇到超过 `scene_gap_ms`
+    /// 的静默时提前结束。单条超出预算的字幕独占一个分块
     ///
     /// # Arguments
     /// * `text_info` - 要分割的文本数组
-    ///
-    /// # Returns
-    /// * `Ok(TextSplitter)` - 成功时返回分割结果
-    /// * `Err(String)` - 失败时返回错误信息
+    /// * `gaps` - 每条字幕与上一条之间静默的毫秒数，缺少时视为 0
+    /// * `settings` - 分块的大小限制
     ///
     /// # Errors
     /// - 当输入文本为空时返回错误
-    /// - 当分割后的组为空时返回错误
-    pub fn split_text(text_info: &[String]) -> Result<Self, String> {
+    /// - 当分块设置无效时返回错误
+    pub fn split_timed(
+        text_info: &[String],
+        gaps: &[i64],
+        settings: &ChunkSettings,
+    ) -> Result<Self> {
         if text_info.is_empty() {
-            return Err("输入文本不能为空".to_string());
+            return Err(StcError::ParseError("输入文本不能为空".to_string()));
         }
-
-        let mut text_splitter = TextSplitter::new();
-
-        for chunk in text_info.chunks(GROUP_SIZE) {
-            let group_text = chunk.join(DELIMITER);
-            text_splitter.split_result.push(group_text);
+        if settings.min_cues == 0 || settings.max_cues < settings.min_cues {
+            return Err(StcError::ConfigError(format!(
+                "无效的分块设置：min_cues = {}，max_cues = {}",
+                settings.min_cues, settings.max_cues
+            )));
         }
 
-        if text_splitter.split_result.is_empty() {
-            return Err("分割后的组不能为空".to_string());
+        let tokens: Vec<usize> = text_info.iter().map(|text| estimate_tokens(text)).collect();
+        let gap = |index: usize| gaps.get(index).copied().unwrap_or(0);
+        let scene_gap = i64::try_from(settings.scene_gap_ms).unwrap_or(i64::MAX);
+        let mut text_splitter = TextSplitter::new();
+        let mut start = 0;
+        while start < text_info.len() {
+            let mut end = start;
+            let mut used = 0;
+            let mut scene_change = false;
+            while end < text_info.len() {
+                let count = end - start;
+                if count >= settings.min_cues {
+                    if count >= settings.max_cues || used + tokens[end] > settings.max_tokens {
+                        break;
+                    }
+                    let half_full =
+                        used * 2 >= settings.max_tokens || count * 2 >= settings.max_cues;
+                    if half_full && gap(end) >= scene_gap {
+                        scene_change = true;
+                        break;
+                    }
+                }
+                used += tokens[end];
+                end += 1;
+            }
+            // 分块已满时在后半部分静默最长的位置断开，静默相同时取最靠后的位置
+            if end < text_info.len() && !scene_change {
+                let lowest = (start + settings.min_cues).max(start + (end - start).div_ceil(2));
+                end = (lowest..=end)
+                    .max_by_key(|&index| gap(index))
+                    .unwrap_or(end);
+            }
+            text_splitter
+                .split_result
+                .push(text_info[start..end].join(DELIMITER));
+            start = end;
         }
 
         Ok(text_splitter)
@@ -61,9 +147,92 @@ mod test {
 
         let text_splitter = TextSplitter::split_text(&subtitle_extractor.text_info).unwrap();
 
+        let cues: usize = text_splitter
+            .split_result
+            .iter()
+            .map(|chunk| chunk.split(DELIMITER).count())
+            .sum();
+        assert_eq!(cues, subtitle_entries.len());
+        assert!(text_splitter
+            .split_result
+            .iter()
+            .all(|chunk| chunk.split(DELIMITER).count() <= DEFAULT_MAX_CUES));
+    }
+
+    #[test]
+    fn test_token_budget() {
+        let long = "word ".repeat(40);
+        let text_info: Vec<String> = ["Hi.", "Yes.", &long, "No.", "Go!", "Run."]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let settings = ChunkSettings {
+            max_tokens: 20,
+            min_cues: 1,
+            max_cues: 2,
+            scene_gap_ms: DEFAULT_SCENE_GAP_MS,
+        };
+
+        let text_splitter = TextSplitter::split_with(&text_info, &settings).unwrap();
+
+        assert_eq!(
+            text_splitter.split_result,
+            vec![
+                "Hi.<T>Yes.".to_string(),
+                long.clone(),
+                "No.<T>Go!".to_string(),
+                "Run.".to_string()
+            ]
+        );
+
+        let settings = ChunkSettings {
+            min_cues: 3,
+            max_cues: 3,
+            ..settings
+        };
+        let text_splitter = TextSplitter::split_with(&text_info, &settings).unwrap();
+        assert_eq!(text_splitter.split_result.len(), 2);
+
+        let invalid = ChunkSettings {
+            min_cues: 4,
+            max_cues: 3,
+            ..settings
+        };
+        assert!(TextSplitter::split_with(&text_info, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_scene_gaps() {
+        let text_info: Vec<String> = (1..=8).map(|i| format!("Line {}.", i)).collect();
+        let settings = ChunkSettings {
+            max_tokens: usize::MAX,
+            min_cues: 2,
+            max_cues: 4,
+            scene_gap_ms: 3000,
+        };
+
+        // 第 4 条前的静默最长，满 4 条时退回到那里断开
+        let gaps = [0, 500, 500, 1500, 400, 400, 400, 400];
+        let text_splitter = TextSplitter::split_timed(&text_info, &gaps, &settings).unwrap();
+        assert_eq!(
+            text_splitter.split_result,
+            vec![
+                "Line 1.<T>Line 2.<T>Line 3.",
+                "Line 4.<T>Line 5.<T>Line 6.<T>Line 7.",
+                "Line 8."
+            ]
+        );
+
+        // 换场时提前结束，但分块至少要用掉一半容量
+        let gaps = [0, 5000, 400, 5000, 400, 400, 400, 400];
+        let text_splitter = TextSplitter::split_timed(&text_info, &gaps, &settings).unwrap();
         assert_eq!(
-            text_splitter.split_result.len(),
-            subtitle_entries.len() / GROUP_SIZE
+            text_splitter.split_result,
+            vec![
+                "Line 1.<T>Line 2.<T>Line 3.",
+                "Line 4.<T>Line 5.<T>Line 6.<T>Line 7.",
+                "Line 8."
+            ]
         );
     }
 }
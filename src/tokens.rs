@@ -0,0 +1,45 @@
+/// 离线估算文本的 token 数量
+///
+/// 按 GPT-4o / Claude 分词器的大致比例估算，不需要下载词表：
+/// - ASCII 文本约 4 个字符 1 个 token
+/// - 中日韩文字约 1 个字符 1 个 token
+/// - 其他字符（西里尔、阿拉伯字母等）约 2 个字符 1 个 token
+///
+/// 只用于划分分块和 dry run 的用量估算，与实际用量有 10%~20% 的误差
+pub fn estimate_tokens(text: &str) -> usize {
+    let (mut ascii, mut cjk, mut other) = (0usize, 0usize, 0usize);
+    for c in text.chars() {
+        match c {
+            c if c.is_ascii() => ascii += 1,
+            c if is_cjk(c) => cjk += 1,
+            _ => other += 1,
+        }
+    }
+    ascii.div_ceil(4) + cjk + other.div_ceil(2)
+}
+
+/// 中日韩文字、假名、谚文和全角标点
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3000..=0x30FF // 标点、平假名、片假名
+        | 0x3400..=0x4DBF // 扩展 A
+        | 0x4E00..=0x9FFF // 基本汉字
+        | 0xAC00..=0xD7AF // 谚文
+        | 0xF900..=0xFAFF // 兼容汉字
+        | 0xFF00..=0xFFEF // 全角字符
+        | 0x20000..=0x2FA1F // 扩展 B 及以后
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("He swore an oath."), 5);
+        assert_eq!(estimate_tokens("他发过誓。"), 5);
+        assert_eq!(estimate_tokens("Он поклялся"), 6);
+    }
+}
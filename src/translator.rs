@@ -1,35 +1,78 @@
-use crate::{openai::OpenAI, TEMPLATES};
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum TranslatorError {
-    #[error("Template rendering failed: {0}")]
-    TemplateError(#[from] tera::Error),
-    #[error("Translation failed: {0}")]
-    TranslationError(String),
-}
-type Result<T> = std::result::Result<T, TranslatorError>;
+use crate::{
+    backend::{TranslationBackend, Usage},
+    cache::Cache,
+    error::{Result, StcError},
+    glossary::{Glossary, Term},
+    retry::RetryPolicy,
+    structured::{self, ResponseMode},
+};
+use std::sync::Arc;
+use tera::Tera;
 
 #[derive(Debug, Clone)]
 pub struct Translator {
     pub translated_result: String,
+    pub usage: Usage,
+    backend: Arc<dyn TranslationBackend>,
+    templates: Arc<Tera>,
+    cache: Option<Arc<Cache>>,
+    retry: Option<Arc<RetryPolicy>>,
+    response_mode: ResponseMode,
+    /// 最近一次请求中原文的分段数量
+    segment_count: usize,
+    glossary: Option<Arc<Glossary>>,
+    /// 当前分块中出现的术语
+    terms: Vec<Term>,
 }
 impl Translator {
-    pub fn new() -> Self {
+    pub fn new(backend: Arc<dyn TranslationBackend>, templates: Arc<Tera>) -> Self {
         Self {
             translated_result: String::new(),
+            usage: Usage::default(),
+            backend,
+            templates,
+            cache: None,
+            retry: None,
+            response_mode: ResponseMode::default(),
+            segment_count: 0,
+            glossary: None,
+            terms: Vec::new(),
         }
     }
 
+    /// 在调用后端前先查找的翻译缓存
+    pub fn cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// 后端请求失败时的重试策略，未设置时只请求一次
+    pub fn retry(mut self, retry: Arc<RetryPolicy>) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// 要求模型返回译文的方式
+    pub fn response_mode(mut self, value: ResponseMode) -> Self {
+        self.response_mode = value;
+        self
+    }
+
+    /// 术语表，分块中出现的术语以 `glossary` 变量传给模板
+    pub fn glossary(mut self, glossary: Arc<Glossary>) -> Self {
+        self.glossary = Some(glossary);
+        self
+    }
+
     /// 处理模板错误并记录详细信息
-    fn handle_template_error(e: tera::Error) -> TranslatorError {
+    fn handle_template_error(e: tera::Error) -> StcError {
         println!("Error: {}", e);
         let mut cause = std::error::Error::source(&e);
         while let Some(e) = cause {
             println!("Reason: {}", e);
             cause = e.source();
         }
-        TranslatorError::TemplateError(e)
+        StcError::TemplateError(e)
     }
 
     /// 格式化用户消息模板
@@ -40,39 +83,93 @@ impl Translator {
         tagged_text: String,
         chunk_to_translate: String,
     ) -> Result<String> {
+        let json = self.response_mode == ResponseMode::Json;
+        // JSON 模式下待翻译部分只以 JSON 对象出现一次，上下文中不再标出
+        let tagged_text = match json {
+            true => tagged_text
+                .replace("<TRANSLATE_THIS>", "")
+                .replace("</TRANSLATE_THIS>", ""),
+            false => tagged_text,
+        };
         let mut context = tera::Context::new();
         context.insert("source_language", source_language);
         context.insert("target_language", target_language);
         context.insert("tagged_text", &tagged_text);
         context.insert("chunk_to_translate", &chunk_to_translate);
+        context.insert("glossary", &self.terms);
+        context.insert("json", &json);
 
-        TEMPLATES
+        self.templates
             .render("user_message.txt", &context)
             .map_err(Self::handle_template_error)
     }
 
+    /// 分段数量不对时追加到用户消息后的说明
+    pub fn format_repair_message(&self, segment_count: usize) -> Result<String> {
+        let mut context = tera::Context::new();
+        context.insert("segment_count", &segment_count);
+        context.insert("json", &(self.response_mode == ResponseMode::Json));
+
+        self.templates
+            .render("repair_message.txt", &context)
+            .map_err(Self::handle_template_error)
+    }
+
     /// 格式化提示模板
     pub fn format_prompt(&self, source_language: &str, target_language: &str) -> Result<String> {
         let mut context = tera::Context::new();
         context.insert("source_language", source_language);
         context.insert("target_language", target_language);
+        context.insert("glossary", &self.terms);
 
-        TEMPLATES
-            .render("prompt.txt", &context)
+        let template = match self.response_mode {
+            ResponseMode::Delimited => "prompt.txt",
+            ResponseMode::Json => "json_prompt.txt",
+        };
+        self.templates
+            .render(template, &context)
             .map_err(Self::handle_template_error)
     }
 
     /// 格式化翻译结果
     pub fn format_translated_result(&mut self) -> String {
-        let result = self
-            .translated_result
+        self.translated_result
             .split("```")
             .filter(|s| !s.trim().is_empty())
             .last()
             .map(|s| s.trim().to_string())
-            .unwrap_or_default();
+            .unwrap_or_default()
+    }
+
+    /// 按原文的分段取出译文，数量对不上或无法解析时返回原因
+    pub fn translated_segments(&mut self) -> std::result::Result<Vec<String>, String> {
+        match self.response_mode {
+            ResponseMode::Delimited => {
+                let segments: Vec<String> = self
+                    .format_translated_result()
+                    .split("<T>")
+                    .map(str::to_string)
+                    .collect();
+                match segments.len() == self.segment_count {
+                    true => Ok(segments),
+                    false => Err(format!(
+                        "expected {} segments, got {}",
+                        self.segment_count,
+                        segments.len()
+                    )),
+                }
+            }
+            ResponseMode::Json => structured::parse(&self.translated_result, self.segment_count),
+        }
+    }
 
-        result
+    /// 把全部译文合并为一段，用于无法按分段取出的单帧字幕
+    pub fn translated_text_lossy(&mut self) -> String {
+        let joined = match self.response_mode {
+            ResponseMode::Delimited => None,
+            ResponseMode::Json => structured::join_values(&self.translated_result),
+        };
+        joined.unwrap_or_else(|| self.format_translated_result().replace("<T>", " "))
     }
 
     /// 执行翻译
@@ -83,22 +180,95 @@ impl Translator {
         tagged_text: String,
         chunk_to_translate: String,
     ) -> Result<()> {
-        let formatted_user_message = self.format_user_message(
-            source_language,
-            target_language,
-            tagged_text,
-            chunk_to_translate,
-        )?;
+        self.segment_count = chunk_to_translate.split("<T>").count();
+        self.terms = self.terms_in(&chunk_to_translate);
+        let chunk = self.chunk_for_mode(chunk_to_translate);
+        let formatted_user_message =
+            self.format_user_message(source_language, target_language, tagged_text, chunk.clone())?;
 
         let formatted_prompt = self.format_prompt(source_language, target_language)?;
+        self.request(formatted_prompt, formatted_user_message, &chunk)
+            .await
+    }
 
-        let openai = OpenAI::new();
-        let translated_result = openai
-            .chat(formatted_prompt, formatted_user_message)
+    /// 使用更严格的说明重新翻译，要求译文的分段数量与原文一致
+    pub async fn translate_strict(
+        &mut self,
+        source_language: &str,
+        target_language: &str,
+        tagged_text: String,
+        chunk_to_translate: String,
+    ) -> Result<()> {
+        self.segment_count = chunk_to_translate.split("<T>").count();
+        self.terms = self.terms_in(&chunk_to_translate);
+        let repair_message = self.format_repair_message(self.segment_count)?;
+        let chunk = self.chunk_for_mode(chunk_to_translate);
+        let formatted_user_message =
+            self.format_user_message(source_language, target_language, tagged_text, chunk.clone())?
+                + &repair_message;
+        let formatted_prompt = self.format_prompt(source_language, target_language)?;
+        self.request(formatted_prompt, formatted_user_message, &chunk)
             .await
-            .map_err(|e| TranslatorError::TranslationError(e.to_string()))?;
+    }
+
+    fn terms_in(&self, chunk_to_translate: &str) -> Vec<Term> {
+        self.glossary
+            .as_ref()
+            .map(|glossary| glossary.terms_in(chunk_to_translate))
+            .unwrap_or_default()
+    }
+
+    /// JSON 模式下把待翻译的分块转换为编号的 JSON 对象
+    fn chunk_for_mode(&self, chunk_to_translate: String) -> String {
+        match self.response_mode {
+            ResponseMode::Delimited => chunk_to_translate,
+            ResponseMode::Json => structured::to_json(&chunk_to_translate),
+        }
+    }
 
-        self.translated_result = translated_result;
+    /// 查找缓存或调用后端，结果保存在 `translated_result` 和 `usage` 中
+    async fn request(
+        &mut self,
+        formatted_prompt: String,
+        formatted_user_message: String,
+        chunk: &str,
+    ) -> Result<()> {
+        let cache_key = Cache::key(&[
+            &formatted_prompt,
+            &formatted_user_message,
+            self.backend.name(),
+            self.backend.model(),
+            &self.backend.parameters(),
+        ]);
+        if let Some(text) = self.cache.as_ref().and_then(|cache| cache.get(&cache_key)) {
+            self.translated_result = text;
+            self.usage = Usage::default();
+            return Ok(());
+        }
+
+        let schema = (self.response_mode == ResponseMode::Json)
+            .then(|| structured::schema(self.segment_count));
+        let chat = || {
+            let (prompt, user_message) = (formatted_prompt.clone(), formatted_user_message.clone());
+            self.backend
+                .chat_chunk(prompt, user_message, chunk, schema.clone())
+        };
+        let response = match &self.retry {
+            Some(retry) => retry.run(chat).await?,
+            None => chat().await?,
+        };
+
+        self.translated_result = response.text;
+        self.usage = response.usage;
+        // 只缓存分段数量正确的回复，需要修复的回复下次仍然重新请求
+        if let Some(cache) = self.cache.clone() {
+            if self.translated_segments().is_ok() {
+                // 缓存写入失败不影响翻译结果
+                if let Err(e) = cache.put(&cache_key, &self.translated_result) {
+                    eprintln!("Failed to write cache: {}", e);
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -106,9 +276,12 @@ impl Translator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::formatter::Formatter;
-    use futures::future;
-    const MIN_SUCCESS_RATE: f64 = 20.0;
+    use crate::{formatter::Formatter, mock::MockBackend};
+
+    fn translator() -> Translator {
+        let templates = crate::template::load_templates(None).expect("Failed to load templates");
+        Translator::new(Arc::new(MockBackend::new()), Arc::new(templates))
+    }
 
     fn setup() -> Formatter {
         let path = std::path::PathBuf::from("test.srt");
@@ -119,13 +292,13 @@ mod tests {
         let text_splitter =
             crate::text_splitter::TextSplitter::split_text(&subtitle_extractor.text_info)
                 .expect("Failed to split text");
-        let formatter = crate::formatter::Formatter::format(0, &text_splitter.split_result);
-        formatter
+        crate::formatter::Formatter::format(0, &text_splitter.split_result)
+            .expect("Failed to format")
     }
     #[test]
     fn test_format_user_message() {
         let formatter = setup();
-        let translator = Translator::new();
+        let translator = translator();
 
         let formatted_user_message = translator.format_user_message(
             "en",
@@ -139,9 +312,34 @@ mod tests {
         assert!(!formatted_user_message.unwrap().is_empty());
     }
 
+    #[test]
+    fn test_format_user_message_json() {
+        let formatter = setup();
+        let translator = translator().response_mode(ResponseMode::Json);
+
+        let formatted_user_message = translator
+            .format_user_message(
+                "en",
+                "zh_CN",
+                formatter.tagged_text,
+                translator.chunk_for_mode(formatter.chunk_to_translate),
+            )
+            .unwrap();
+
+        assert_eq!(
+            formatted_user_message.matches("<TRANSLATE_THIS>").count(),
+            1
+        );
+        assert_eq!(
+            formatted_user_message.matches("</TRANSLATE_THIS>").count(),
+            1
+        );
+        assert!(formatted_user_message.contains("<TRANSLATE_THIS>\n\n{"));
+    }
+
     #[test]
     fn test_format_prompt() {
-        let translator = Translator::new();
+        let translator = translator();
 
         let formatted_prompt = translator.format_prompt("en", "zh_CN");
 
@@ -152,7 +350,7 @@ mod tests {
     #[tokio::test]
     async fn test_translate() {
         let formatter = setup();
-        let mut translator = Translator::new();
+        let mut translator = translator();
 
         translator
             .translate(
@@ -168,41 +366,71 @@ mod tests {
         assert!(!translator.translated_result.is_empty());
     }
 
-    pub(crate) async fn test_format_translated_result() -> bool {
-        let formatter = setup();
-        let mut translator = Translator::new();
+    #[tokio::test]
+    async fn test_translated_segments_from_malformed_responses() {
+        let chunk = "He'll come, Lancelot.<T>He swore an oath.<T>Charge!";
+        let responses = [
+            // 多轮翻译时取最后一个代码块
+            "【翻译】\n```\n草稿<T>草稿<T>草稿\n```\n【思考】定稿\n```\n他会来的，兰斯洛特。<T>他发过誓。<T>冲！\n```",
+            // 代码块没有闭合
+            "```\n他会来的，兰斯洛特。<T>他发过誓。<T>冲！",
+            // 没有代码块
+            "他会来的，兰斯洛特。<T>他发过誓。<T>冲！\n",
+            // 少了一个分隔符
+            "```\n他会来的，兰斯洛特。<T>他发过誓。冲！\n```",
+        ];
+        let backend = Arc::new(MockBackend::with_responses(
+            responses.iter().map(|s| s.to_string()).collect(),
+        ));
+        let templates = crate::template::load_templates(None).unwrap();
+        let mut translator = Translator::new(backend, Arc::new(templates));
+        let expected = ["他会来的，兰斯洛特。", "他发过誓。", "冲！"];
 
+        for _ in 0..3 {
+            translator
+                .translate("en", "zh_CN", chunk.to_string(), chunk.to_string())
+                .await
+                .unwrap();
+            assert_eq!(translator.translated_segments().unwrap(), expected);
+        }
         translator
-            .translate(
-                "en",
-                "zh_CN",
-                formatter.tagged_text,
-                formatter.chunk_to_translate,
-            )
+            .translate("en", "zh_CN", chunk.to_string(), chunk.to_string())
             .await
-            .expect("Failed to translate");
-
-        let formatted_result = translator.format_translated_result();
-        formatted_result.split("<T>").count() == crate::GROUP_SIZE
+            .unwrap();
+        assert_eq!(
+            translator.translated_segments(),
+            Err("expected 3 segments, got 2".to_string())
+        );
+        assert_eq!(
+            translator.translated_text_lossy(),
+            "他会来的，兰斯洛特。 他发过誓。冲！"
+        );
     }
 
     #[tokio::test]
-    async fn test_format_translated_result_multiple_times() {
-        const TOTAL_RUNS: usize = 10;
-        let handles: Vec<_> = (0..TOTAL_RUNS)
-            .map(|_| test_format_translated_result())
-            .collect();
-
-        let results = future::join_all(handles).await;
-        let success_count = results.iter().filter(|&&success| success).count();
-        let success_rate = (success_count as f64 / TOTAL_RUNS as f64) * 100.0;
-
-        eprintln!("\nSuccess rate: {:.2}%\n", success_rate);
-        assert!(
-            success_rate >= MIN_SUCCESS_RATE,
-            "Success rate {:.2}% is below the acceptable threshold of {:.2}%",
-            success_rate,
-            MIN_SUCCESS_RATE
+    async fn test_cache_only_valid_responses() {
+        let chunk = "He swore an oath.<T>Charge!";
+        let backend = Arc::new(MockBackend::with_responses(vec![
+            "```\n他发过誓。冲！\n```".to_string(),
+            "```\n他发过誓。<T>冲！\n```".to_string(),
+        ]));
+        let templates = crate::template::load_templates(None).unwrap();
+        let cache = Arc::new(Cache::new(crate::mock::temp_dir("translator_cache")));
+        let mut translator =
+            Translator::new(backend.clone(), Arc::new(templates)).cache(cache.clone());
+
+        for _ in 0..3 {
+            translator
+                .translate("en", "zh_CN", chunk.to_string(), chunk.to_string())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            translator.translated_segments().unwrap(),
+            ["他发过誓。", "冲！"]
         );
+        assert_eq!(backend.requests().len(), 2);
+        assert_eq!(cache.stats().unwrap().entries, 1);
     }
 }
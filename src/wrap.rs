@@ -0,0 +1,273 @@
+use crate::tokens::is_cjk;
+
+/// 默认每帧字幕最多的行数
+pub const DEFAULT_MAX_LINES: usize = 2;
+
+/// 译文的换行规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrapSettings {
+    /// 每行最大显示宽度，中日韩文字和全角字符按 2 计算
+    pub max_width: usize,
+    /// 每帧字幕最多的行数，文本太长时允许超出宽度，但不会超出行数
+    pub max_lines: usize,
+}
+
+/// 行首不能出现的标点
+const NO_LINE_START: &[char] = &[
+    '，', '。', '、', '！', '？', '：', '；', '）', '」', '』', '”', '’', '…', '》', '〉', '】',
+    ',', '.', '!', '?', ':', ';', ')',
+];
+/// 行尾不能出现的标点
+const NO_LINE_END: &[char] = &['（', '「', '『', '“', '‘', '《', '〈', '【', '('];
+/// 均衡的换行位置附近这么多显示宽度以内有标点时，优先在标点之后换行
+const PUNCTUATION_TOLERANCE: usize = 10;
+
+/// 一个不可拆分的单元：一个字符或一个 `[[n]]` 占位符
+#[derive(Debug)]
+struct Unit<'a> {
+    text: &'a str,
+    width: usize,
+    /// 占位符没有宽度，换行时跳过
+    placeholder: bool,
+}
+
+fn units(text: &str) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let placeholder = rest
+            .strip_prefix("[[")
+            .and_then(|inner| inner.split_once("]]"))
+            .filter(|(number, _)| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+            .map(|(number, _)| number.len() + 4);
+        let len = placeholder.unwrap_or(c.len_utf8());
+        units.push(Unit {
+            text: &rest[..len],
+            width: match placeholder {
+                Some(_) => 0,
+                None => display_width(c),
+            },
+            placeholder: placeholder.is_some(),
+        });
+        rest = &rest[len..];
+    }
+    units
+}
+
+fn display_width(c: char) -> usize {
+    if is_cjk(c) {
+        2
+    } else {
+        1
+    }
+}
+
+/// 去掉原有的换行，中日韩文字之间直接连接，其他文字之间用空格连接
+fn unwrap_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .fold(String::new(), |mut joined, line| {
+            let cjk_boundary = joined.chars().last().is_some_and(is_cjk)
+                && line.chars().next().is_some_and(is_cjk);
+            if !joined.is_empty() && !cjk_boundary {
+                joined.push(' ');
+            }
+            joined.push_str(line);
+            joined
+        })
+}
+
+/// 能否在第 `position` 个单元之前换行
+///
+/// 空白之后可以换行；中日韩文字之间可以换行，但要避开行首和行尾禁用的标点
+fn can_break(units: &[Unit], position: usize) -> bool {
+    let previous = units[..position].iter().rev().find(|u| !u.placeholder);
+    let next = units[position..].iter().find(|u| !u.placeholder);
+    let (Some(previous), Some(next)) = (previous, next) else {
+        return false;
+    };
+    let (Some(p), Some(n)) = (previous.text.chars().next(), next.text.chars().next()) else {
+        return false;
+    };
+    if p.is_whitespace() {
+        return !n.is_whitespace();
+    }
+    (is_cjk(p) || is_cjk(n))
+        && !n.is_whitespace()
+        && !NO_LINE_START.contains(&n)
+        && !NO_LINE_END.contains(&p)
+}
+
+/// 第 `position` 个单元之前是否紧跟在标点之后，中间可以有空白和占位符
+fn after_punctuation(units: &[Unit], position: usize) -> bool {
+    units[..position]
+        .iter()
+        .rev()
+        .find(|u| !u.placeholder && !u.text.trim().is_empty())
+        .and_then(|u| u.text.chars().next())
+        .is_some_and(|c| NO_LINE_START.contains(&c))
+}
+
+/// 单元 `start..end` 组成一行时的显示宽度，不计首尾空白
+fn line_width(units: &[Unit], start: usize, end: usize) -> usize {
+    let line = &units[start..end];
+    let first = line.iter().position(|u| !u.text.trim().is_empty());
+    let last = line.iter().rposition(|u| !u.text.trim().is_empty());
+    match (first, last) {
+        (Some(first), Some(last)) => line[first..=last].iter().map(|u| u.width).sum(),
+        _ => 0,
+    }
+}
+
+/// 在 `breaks` 中选出 `lines - 1` 个换行位置，返回超出 `max_width` 的总宽度和换行位置
+///
+/// 先让超出的宽度尽量少，再让各行宽度的平方和尽量小，使各行宽度接近；
+/// 不在标点之后的换行额外计入代价，均衡位置附近有标点时在标点之后换行
+fn balance(
+    units: &[Unit],
+    breaks: &[usize],
+    lines: usize,
+    max_width: usize,
+) -> Option<(usize, Vec<usize>)> {
+    // 候选位置：开头、所有可换行的位置、结尾
+    let points: Vec<usize> = std::iter::once(0)
+        .chain(breaks.iter().copied())
+        .chain(std::iter::once(units.len()))
+        .collect();
+    let last = points.len() - 1;
+    // 换行位置偏离均衡位置 d 时平方和约增加 2d²
+    let penalty = 2 * PUNCTUATION_TOLERANCE * PUNCTUATION_TOLERANCE;
+    // best[k][j]：前 k 行恰好结束在 points[j] 时的 (超出的宽度, 代价, 上一个位置)
+    let mut best: Vec<Vec<Option<(usize, usize, usize)>>> =
+        vec![vec![None; points.len()]; lines + 1];
+    best[0][0] = Some((0, 0, 0));
+    for k in 1..=lines {
+        for j in 1..=last {
+            let break_cost = match j < last && !after_punctuation(units, points[j]) {
+                true => penalty,
+                false => 0,
+            };
+            for i in 0..j {
+                let Some((overflow, cost, _)) = best[k - 1][i] else {
+                    continue;
+                };
+                let width = line_width(units, points[i], points[j]);
+                let candidate = (
+                    overflow + width.saturating_sub(max_width),
+                    cost + width * width + break_cost,
+                    i,
+                );
+                if best[k][j].is_none_or(|(o, c, _)| (candidate.0, candidate.1) < (o, c)) {
+                    best[k][j] = Some(candidate);
+                }
+            }
+        }
+    }
+    let (overflow, _, _) = best[lines][last]?;
+    let mut cuts = Vec::with_capacity(lines - 1);
+    let mut j = last;
+    for k in (1..=lines).rev() {
+        let (_, _, i) = best[k][j]?;
+        if k > 1 {
+            cuts.push(points[i]);
+        }
+        j = i;
+    }
+    cuts.reverse();
+    Some((overflow, cuts))
+}
+
+/// 按显示宽度重新为一帧译文换行，换行符为 `\n`
+///
+/// 使用能放下整段文本的最少行数，各行宽度尽量均衡。放不下时使用 `max_lines` 行
+pub fn wrap(text: &str, settings: &WrapSettings) -> String {
+    let text = unwrap_lines(text);
+    let units = units(&text);
+    let total = line_width(&units, 0, units.len());
+    if total <= settings.max_width || settings.max_lines <= 1 {
+        return text;
+    }
+    let breaks: Vec<usize> = (1..units.len()).filter(|&p| can_break(&units, p)).collect();
+    let fewest = total.div_ceil(settings.max_width.max(1));
+    let mut chosen = None;
+    for lines in fewest.min(settings.max_lines)..=settings.max_lines.min(breaks.len() + 1) {
+        if let Some((overflow, cuts)) = balance(&units, &breaks, lines, settings.max_width) {
+            let fits = overflow == 0;
+            chosen = Some(cuts);
+            if fits {
+                break;
+            }
+        }
+    }
+    let Some(cuts) = chosen else {
+        return text;
+    };
+    std::iter::once(0)
+        .chain(cuts.iter().copied())
+        .zip(cuts.iter().copied().chain(std::iter::once(units.len())))
+        .map(|(start, end)| {
+            units[start..end]
+                .iter()
+                .map(|u| u.text)
+                .collect::<String>()
+                .trim()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SETTINGS: WrapSettings = WrapSettings {
+        max_width: 20,
+        max_lines: 2,
+    };
+
+    #[test]
+    fn test_wrap_cjk() {
+        // 宽度 40，分成两行，每行 10 个字，在标点之后换行会超出宽度
+        assert_eq!(
+            wrap("我们英国人正在打一场殊死之战，你一定要明白", &SETTINGS),
+            "我们英国人正在打一场\n殊死之战，你一定要明白"
+        );
+        // 放得下时优先在标点之后换行
+        let wide = WrapSettings {
+            max_width: 32,
+            max_lines: 2,
+        };
+        assert_eq!(
+            wrap("但你要明白，我们英国人正在打一场殊死之战", &wide),
+            "但你要明白，\n我们英国人正在打一场殊死之战"
+        );
+        assert_eq!(wrap("他发过誓。", &SETTINGS), "他发过誓。");
+        // 原有的换行先去掉再重新换行
+        assert_eq!(wrap("他发过\n誓。", &SETTINGS), "他发过誓。");
+    }
+
+    #[test]
+    fn test_wrap_latin() {
+        assert_eq!(
+            wrap(
+                "But you have to understand, we Brits are fighting",
+                &SETTINGS
+            ),
+            "But you have to understand,\nwe Brits are fighting"
+        );
+        assert_eq!(
+            wrap("Where in hell\nis he?", &SETTINGS),
+            "Where in hell is he?"
+        );
+    }
+
+    #[test]
+    fn test_wrap_keeps_placeholders() {
+        assert_eq!(
+            wrap("[[1]]魔法确实存在，很久以前就被人发现了[[2]]", &SETTINGS),
+            "[[1]]魔法确实存在，\n很久以前就被人发现了[[2]]"
+        );
+    }
+}
@@ -1,11 +1,481 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{fmt, fs::File, io::Write, path::Path, path::PathBuf};
+
+use serde::Deserialize;
+use subparse::{
+    timetypes::{TimePoint, TimeSpan},
+    SubtitleEntry, SubtitleFile,
+};
+
+use crate::{
+    ass::{self, AssStyles},
+    error::{Result, StcError},
+    parse::DEFAULT_FPS,
+    subtitle_combiner::{CombinedCue, Layout},
+};
+
+/// 输出的字幕格式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// SubRip (.srt)
+    Srt,
+    /// Advanced SubStation Alpha (.ass)
+    #[serde(alias = "ssa")]
+    #[value(alias = "ssa")]
+    Ass,
+    /// WebVTT (.vtt)
+    Vtt,
+    /// MicroDVD (.sub)
+    #[serde(rename = "microdvd")]
+    #[value(name = "microdvd")]
+    MicroDvd,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Srt => "srt",
+            OutputFormat::Ass => "ass",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::MicroDvd => "microdvd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl OutputFormat {
+    /// 根据文件扩展名推断格式
+    ///
+    /// # Errors
+    /// 扩展名无法识别时返回 `StcError::ConfigError`
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path
+            .extension()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("srt") => Ok(OutputFormat::Srt),
+            Some("ass") | Some("ssa") => Ok(OutputFormat::Ass),
+            Some("vtt") => Ok(OutputFormat::Vtt),
+            Some("sub") => Ok(OutputFormat::MicroDvd),
+            _ => Err(StcError::ConfigError(format!(
+                "Cannot infer the output format from {}, use --output-format",
+                path.display()
+            ))),
+        }
+    }
+
+    /// 解析得到的字幕文件对应的格式
+    ///
+    /// # Errors
+    /// VobSub 和 IDX 图片字幕没有可以写回的文本，返回 `StcError::ParseError`
+    pub fn from_subtitle_file(file: &SubtitleFile) -> Result<Self> {
+        match file {
+            SubtitleFile::SubRipFile(_) => Ok(OutputFormat::Srt),
+            SubtitleFile::SubStationAlpha(_) => Ok(OutputFormat::Ass),
+            SubtitleFile::MicroDVDFile(_) => Ok(OutputFormat::MicroDvd),
+            SubtitleFile::VobSubIdxFile(_) | SubtitleFile::VobSubSubFile(_) => Err(
+                StcError::ParseError("VobSub/IDX image subtitles cannot be translated".to_string()),
+            ),
+        }
+    }
+
+    /// 输出文件的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Srt => "srt",
+            OutputFormat::Ass => "ass",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::MicroDvd => "sub",
+        }
+    }
+}
+
+/// 按输出格式和双语布局生成字幕文件
+#[derive(Debug, Clone)]
+pub struct Writer {
+    format: OutputFormat,
+    layout: Layout,
+    ass_styles: AssStyles,
+}
 
-pub struct Writer {}
 impl Writer {
-    pub fn write_file(content: String, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            layout: Layout::default(),
+            ass_styles: AssStyles::default(),
+        }
+    }
+
+    /// 双语字幕的排列方式
+    pub fn layout(mut self, value: Layout) -> Self {
+        self.layout = value;
+        self
+    }
+
+    /// 新建 ASS 文件时译文和原文使用的样式
+    pub fn ass_styles(mut self, value: AssStyles) -> Self {
+        self.ass_styles = value;
+        self
+    }
+
+    pub fn write_file(content: String, path: PathBuf) -> Result<()> {
         let mut file = File::create(path)?;
         file.write_all(content.as_bytes())?;
         eprintln!("File written successfully");
         Ok(())
     }
+
+    /// 生成字幕内容
+    ///
+    /// `original` 与输出格式相同时只替换其中的文本，保留 ASS 的文件头、样式和事件字段，
+    /// 并加入译文和原文的样式，在对白事件中用覆盖标签切换，其他样式的事件保留原样式；
+    /// 新建的 ASS 文件中译文和原文是使用不同样式的两个事件。
+    /// MicroDVD 同样只替换文本，保留帧率和格式代码，未翻译的字幕保持原样
+    pub fn render(
+        &self,
+        cues: &[(TimeSpan, CombinedCue)],
+        original: Option<&SubtitleFile>,
+    ) -> Result<String> {
+        match (self.format, original) {
+            (OutputFormat::Ass, Some(file @ SubtitleFile::SubStationAlpha(_))) => {
+                let data = file
+                    .to_data()
+                    .map_err(|e| StcError::ParseError(format!("{:?}", e)))?;
+                let styles = ass::event_styles(&String::from_utf8_lossy(&data));
+                let dialogue = ass::dialogue_style(&styles);
+                let entries: Vec<SubtitleEntry> = cues
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (timespan, cue))| {
+                        let restyle = styles.get(index).map(String::as_str) == dialogue;
+                        SubtitleEntry {
+                            timespan: *timespan,
+                            line: Some(ass::styled_text(cue, self.layout, restyle)),
+                        }
+                    })
+                    .collect();
+                Self::update_entries(&mut file.clone(), &entries)
+                    .map(|content| self.ass_styles.add_to(&content))
+            }
+            (OutputFormat::Ass, _) => Ok(ass::render(&self.ass_styles, cues, self.layout)),
+            (OutputFormat::MicroDvd, Some(file @ SubtitleFile::MicroDVDFile(_))) => {
+                // subparse 把帧换算成毫秒时向下取整，写回时又向下取整成帧，
+                // 加 1 毫秒让写回的帧号与原文件相同
+                let later = |t: TimePoint| TimePoint::from_msecs(t.msecs() + 1);
+                let entries: Vec<SubtitleEntry> = cues
+                    .iter()
+                    .map(|(timespan, cue)| SubtitleEntry {
+                        timespan: TimeSpan::new(later(timespan.start), later(timespan.end)),
+                        line: cue
+                            .translated
+                            .as_ref()
+                            .map(|_| self.layout.arrange(cue).replace('\n', "|")),
+                    })
+                    .collect();
+                Self::update_entries(&mut file.clone(), &entries)
+            }
+            (OutputFormat::Srt, _) => Ok(Self::render_srt(&self.entries(cues, original))),
+            (OutputFormat::Vtt, _) => Ok(Self::render_vtt(&self.entries(cues, original))),
+            (OutputFormat::MicroDvd, _) => Ok(Self::render_microdvd(&self.entries(cues, original))),
+        }
+    }
+
+    /// 按布局把每条字幕排成一段文本，SSA/ASS 原文中的 `\N`、`\n` 换成换行符
+    fn entries(
+        &self,
+        cues: &[(TimeSpan, CombinedCue)],
+        original: Option<&SubtitleFile>,
+    ) -> Vec<SubtitleEntry> {
+        let ass = matches!(original, Some(SubtitleFile::SubStationAlpha(_)));
+        cues.iter()
+            .map(|(timespan, cue)| {
+                let text = self.layout.arrange(cue);
+                let text = if ass {
+                    text.replace("\\N", "\n").replace("\\n", "\n")
+                } else {
+                    text
+                };
+                SubtitleEntry {
+                    timespan: *timespan,
+                    line: Some(text),
+                }
+            })
+            .collect()
+    }
+
+    fn update_entries(file: &mut SubtitleFile, cues: &[SubtitleEntry]) -> Result<String> {
+        let count = file
+            .get_subtitle_entries()
+            .map_err(|e| StcError::ParseError(format!("{:?}", e)))?
+            .len();
+        if count != cues.len() {
+            return Err(StcError::AlignmentError(format!(
+                "Expected {} cues, got {}",
+                count,
+                cues.len()
+            )));
+        }
+        file.update_subtitle_entries(cues)
+            .and_then(|_| file.to_data())
+            .map(|data| String::from_utf8_lossy(&data).into_owned())
+            .map_err(|e| StcError::ParseError(format!("Failed to write subtitle: {:?}", e)))
+    }
+
+    fn cue_text(cue: &SubtitleEntry) -> String {
+        cue.line.clone().unwrap_or_default()
+    }
+
+    fn render_srt(cues: &[SubtitleEntry]) -> String {
+        let texts: Vec<String> = cues.iter().map(Self::cue_text).collect();
+        crate::srt::serialize(
+            cues.iter()
+                .map(|cue| cue.timespan)
+                .zip(texts.iter().map(String::as_str)),
+            1,
+        )
+    }
+
+    fn render_vtt(cues: &[SubtitleEntry]) -> String {
+        let mut content = String::from("WEBVTT\n\n");
+        for cue in cues {
+            content.push_str(&format!(
+                "{} --> {}\n{}\n\n",
+                vtt_time(cue.timespan.start),
+                vtt_time(cue.timespan.end),
+                Self::cue_text(cue)
+            ));
+        }
+        content
+    }
+
+    fn render_microdvd(cues: &[SubtitleEntry]) -> String {
+        let frame = |t: TimePoint| (t.msecs() as f64 * DEFAULT_FPS / 1000.0).round() as i64;
+        cues.iter()
+            .map(|cue| {
+                format!(
+                    "{{{}}}{{{}}}{}\n",
+                    frame(cue.timespan.start),
+                    frame(cue.timespan.end),
+                    Self::cue_text(cue).replace('\n', "|")
+                )
+            })
+            .collect()
+    }
+}
+
+/// WebVTT 时间格式 `HH:MM:SS.mmm`
+fn vtt_time(t: TimePoint) -> String {
+    let t = if t.msecs() < 0 {
+        TimePoint::from_msecs(0)
+    } else {
+        t
+    };
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        t.hours(),
+        t.mins_comp(),
+        t.secs_comp(),
+        t.msecs_comp()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cue(
+        start: i64,
+        end: i64,
+        translated: Option<&str>,
+        original: &str,
+    ) -> (TimeSpan, CombinedCue) {
+        (
+            TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end)),
+            CombinedCue {
+                translated: translated.map(str::to_string),
+                original: original.to_string(),
+            },
+        )
+    }
+
+    fn cues() -> Vec<(TimeSpan, CombinedCue)> {
+        vec![
+            cue(
+                94095,
+                96180,
+                Some("你到底在哪？"),
+                "Where in hell is your magician?",
+            ),
+            cue(3723004, 3725000, None, "<i>Hello</i>"),
+        ]
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        let format = |path: &str| OutputFormat::from_path(Path::new(path)).ok();
+
+        assert_eq!(format("a.ASS"), Some(OutputFormat::Ass));
+        assert_eq!(format("a.ssa"), Some(OutputFormat::Ass));
+        assert_eq!(format("a.sub"), Some(OutputFormat::MicroDvd));
+        assert_eq!(format("a.srt"), Some(OutputFormat::Srt));
+        assert_eq!(format("a.idx"), None);
+        assert_eq!(format("a"), None);
+    }
+
+    #[test]
+    fn test_render_vtt() {
+        let content = Writer::new(OutputFormat::Vtt)
+            .render(&cues(), None)
+            .unwrap();
+
+        assert!(content.starts_with("WEBVTT\n\n00:01:34.095 --> 00:01:36.180\n你到底在哪？\n"));
+        assert!(content.contains("01:02:03.004 --> 01:02:05.000"));
+    }
+
+    #[test]
+    fn test_render_srt_line_breaks() {
+        let ass = subparse::parse_str(
+            subparse::SubtitleFormat::SubStationAlpha,
+            &std::fs::read_to_string("test.ass").unwrap(),
+            DEFAULT_FPS,
+        )
+        .unwrap();
+        let cues = [cue(0, 1000, None, "Where\\Nis he?")];
+        let writer = Writer::new(OutputFormat::Srt);
+
+        assert!(writer
+            .render(&cues, Some(&ass))
+            .unwrap()
+            .contains("Where\nis he?"));
+        assert!(writer
+            .render(&cues, None)
+            .unwrap()
+            .contains("Where\\Nis he?"));
+    }
+
+    #[test]
+    fn test_render_ass_from_srt() {
+        let content = Writer::new(OutputFormat::Ass)
+            .render(&cues(), None)
+            .unwrap();
+
+        assert!(content.starts_with("[Script Info]"));
+        assert!(
+            content.contains("Dialogue: 0,0:01:34.09,0:01:36.18,Translated,,0,0,0,,你到底在哪？\n")
+        );
+        assert!(content.contains(",Original,,0,0,0,,{\\i1}Hello{\\i0}\n"));
+    }
+
+    #[test]
+    fn test_render_ass_keeps_original() {
+        let original = subparse::parse_str(
+            subparse::SubtitleFormat::SubStationAlpha,
+            "[Script Info]\nTitle: test\n\n[V4+ Styles]\nFormat: Name, Fontname\nStyle: Sign,Arial\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:01:34.09,0:01:36.18,Sign,,0,0,0,,Where\\Nis he?\n",
+            DEFAULT_FPS,
+        )
+        .unwrap();
+        let cue = cue(94090, 96180, Some("他在哪？"), "Where\\Nis he?");
+
+        let content = Writer::new(OutputFormat::Ass)
+            .render(&[cue], Some(&original))
+            .unwrap();
+
+        assert!(content.contains("Title: test"));
+        assert!(
+            content.contains("Style: Sign,Arial\nStyle: Translated,Arial\nStyle: Original,Arial\n")
+        );
+        assert!(
+            content.contains(",Sign,,0,0,0,,{\\rTranslated}他在哪？\\N{\\rOriginal}Where\\Nis he?")
+        );
+    }
+
+    #[test]
+    fn test_render_ass_input_styles() {
+        let original = subparse::parse_str(
+            subparse::SubtitleFormat::SubStationAlpha,
+            &std::fs::read_to_string("test.ass").unwrap(),
+            DEFAULT_FPS,
+        )
+        .unwrap();
+        let cues: Vec<(TimeSpan, CombinedCue)> = original
+            .get_subtitle_entries()
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let translated = match i {
+                    1 => Some("他发过誓。".to_string()),
+                    2 => Some("牛津大学".to_string()),
+                    _ => None,
+                };
+                let cue = CombinedCue {
+                    translated,
+                    original: entry.line.unwrap_or_default(),
+                };
+                (entry.timespan, cue)
+            })
+            .collect();
+
+        let content = Writer::new(OutputFormat::Ass)
+            .layout(Layout::OriginalAbove)
+            .render(&cues, Some(&original))
+            .unwrap();
+
+        assert!(content.contains("Style: Sign,Arial,48,"));
+        assert!(content.contains(
+            "Style: Translated,Arial,64,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,1,2,20,20,30,1\n"
+        ));
+        assert!(content.contains("Style: Original,Arial,44,&H00C8C8C8,"));
+        assert!(content.find("Style: Original").unwrap() < content.find("[Events]").unwrap());
+        assert!(content.contains(
+            ",Default,,0,0,0,,{\\rOriginal}He swore an oath.\\N{\\rTranslated}他发过誓。"
+        ));
+        assert!(content.contains(",Sign,,0,0,0,,{\\an8}Oxford University\\N牛津大学\n"));
+    }
+
+    #[test]
+    fn test_render_microdvd() {
+        let content = Writer::new(OutputFormat::MicroDvd)
+            .layout(Layout::OriginalAbove)
+            .render(&cues(), None)
+            .unwrap();
+
+        assert!(content.starts_with("{2352}{2405}Where in hell is your magician?|你到底在哪？\n"));
+    }
+
+    #[test]
+    fn test_render_microdvd_keeps_original() {
+        let original = subparse::parse_str(
+            subparse::SubtitleFormat::MicroDVD,
+            "{1}{1}23.976\n{100}{150}{y:i}Where is he?\n{200}{250}Hello\n",
+            23.976,
+        )
+        .unwrap();
+        let cues: Vec<(TimeSpan, CombinedCue)> = original
+            .get_subtitle_entries()
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let cue = CombinedCue {
+                    translated: (i == 1).then(|| "他在哪？".to_string()),
+                    original: entry.line.unwrap_or_default(),
+                };
+                (entry.timespan, cue)
+            })
+            .collect();
+
+        let content = Writer::new(OutputFormat::MicroDvd)
+            .layout(Layout::OriginalAbove)
+            .render(&cues, Some(&original))
+            .unwrap();
+
+        assert_eq!(
+            content,
+            "{1}{1}23.976\n{100}{150}{y:i}Where is he?|他在哪？\n{200}{250}Hello"
+        );
+    }
 }